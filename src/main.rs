@@ -1,5 +1,6 @@
+use std::path::Path;
 use std::time::Instant;
-use std::{env, path};
+use std::{env, path, process};
 
 use ggez::event::{
     Axis, Button, ErrorOrigin, EventHandler, GamepadId, KeyCode, KeyMods, MouseButton,
@@ -16,18 +17,29 @@ use world::position::Position;
 
 use crate::objects::entities::Entities;
 use crate::objects::sprite_atlas::SpriteAtlas;
+use crate::save::game_save::GameSave;
+use crate::save::{SaveMarker, SaveMarkerAllocator};
 use crate::systems::ai_system::AiSystem;
 use crate::systems::chunk_system::{ChunkLoader, ChunkSystem};
 use crate::systems::control_system::{Control, ControlSystem, Keyboard};
+use crate::systems::flow_field_system::FlowFieldSystem;
+use crate::systems::fov_system::FovSystem;
 use crate::systems::health_system::HealthSystem;
+use crate::systems::lighting_system::LightingSystem;
 use crate::systems::movement_system::{Movement, MovementSystem};
+use crate::systems::portal_graph_system::PortalGraphSystem;
 use crate::systems::render_system::RenderSystem;
+use crate::systems::spatial_index_system::SpatialIndexSystem;
+use crate::systems::turn_system::{Turn, TurnSystem};
 use crate::world::chunk::Chunk;
+use crate::world::generation::GeneratorKind;
 use crate::world::position::{ChunkPosition, TilePosition, WorldPosition};
 use crate::world::tile::Tile;
+use crate::world::tile_registry::TileRegistry;
 
 pub mod gui;
 pub mod objects;
+pub mod save;
 pub mod systems;
 pub mod utils;
 pub mod world;
@@ -56,8 +68,13 @@ impl Rusted {
     pub fn new(ctx: &mut Context) -> Self {
         let mut world = World::new();
 
-        let mut level = Level::new(rand::random::<i32>());
-        level.load_chunk(pos!(0, 0));
+        let mut level = Level::new(
+            rand::random::<i32>(),
+            GeneratorKind::Layered(vec![GeneratorKind::Biomes, GeneratorKind::Structures]),
+        );
+        if let Err(e) = level.load_chunk(pos!(0, 0)) {
+            println!("Failed to load chunk {:?}: {}", pos!(0, 0), e);
+        }
 
         let camera = Camera {
             pos: Position { x: 0, y: 0 },
@@ -80,25 +97,55 @@ impl Rusted {
         world.insert(Keyboard::default());
         world.insert(atlas_resource);
         world.insert(camera_resource);
+        world.insert(SaveMarkerAllocator::new());
+        world.register::<SaveMarker>();
 
         let mut dispatcher = DispatcherBuilder::new()
-            .with(ControlSystem, "control", &[])
-            .with(AiSystem, "ai", &[])
+            .with(TurnSystem, "turn", &[])
+            .with(ControlSystem, "control", &["turn"])
+            .with(FlowFieldSystem::default(), "flow_field", &[])
+            .with(PortalGraphSystem::default(), "portal_graph", &[])
+            .with(
+                AiSystem::default(),
+                "ai",
+                &["turn", "flow_field", "portal_graph"],
+            )
             .with(MovementSystem, "movement", &["control", "ai"])
-            .with(ChunkSystem, "chunk", &[])
+            .with(SpatialIndexSystem, "spatial_index", &["movement"])
+            .with(ChunkSystem, "chunk", &["spatial_index"])
+            .with(FovSystem::default(), "fov", &["movement", "chunk"])
+            .with(
+                LightingSystem::default(),
+                "lighting",
+                &["movement", "chunk"],
+            )
             .with(HealthSystem, "health", &[])
             .with_thread_local(RenderSystem)
             .build();
 
         dispatcher.setup(&mut world);
 
-        // Spawn Player
-        let e = Entities::create_player(&mut world);
-        Entities::create_ai(&mut world, e);
-        Entities::create_ai(&mut world, e);
-        Entities::create_ai(&mut world, e);
-        Entities::create_ai(&mut world, e);
-        Entities::create_ai(&mut world, e);
+        // Resume a saved game when a save directory is passed as argument
+        let loaded = match env::args().nth(1) {
+            Some(dir) => match GameSave::load(&mut world, Path::new(&dir)) {
+                Ok(()) => true,
+                Err(e) => {
+                    println!("Failed to load save {}: {}", dir, e);
+                    false
+                }
+            },
+            None => false,
+        };
+
+        if !loaded {
+            // Spawn Player
+            let e = Entities::create_player(&mut world);
+            Entities::create_ai(&mut world, e);
+            Entities::create_ai(&mut world, e);
+            Entities::create_ai(&mut world, e);
+            Entities::create_ai(&mut world, e);
+            Entities::create_ai(&mut world, e);
+        }
 
         Rusted {
             world,
@@ -110,6 +157,12 @@ impl Rusted {
             dispatcher,
         }
     }
+
+    fn save_game(&self) {
+        if let Err(e) = GameSave::save(&self.world) {
+            println!("Failed to save game: {}", e);
+        }
+    }
 }
 
 impl EventHandler for Rusted {
@@ -120,6 +173,21 @@ impl EventHandler for Rusted {
         keymods: KeyMods,
         repeat: bool,
     ) {
+        if keycode == KeyCode::F5 && !repeat {
+            self.save_game();
+        }
+
+        if keycode == KeyCode::T && !repeat {
+            let mut turn = self.world.write_resource::<Turn>();
+            turn.toggle_mode();
+            println!("Switched to {:?} mode", turn.mode);
+        }
+
+        self.world
+            .write_resource::<Keyboard>()
+            .just_pressed
+            .insert(keycode);
+
         const MTP: u32 = 20;
 
         while timer::check_update_time(ctx, MTP) {
@@ -131,10 +199,8 @@ impl EventHandler for Rusted {
             {
                 let mut world = &self.world;
                 let mut keyboard = world.write_resource::<Keyboard>();
-                *keyboard = Keyboard {
-                    pressed_keys: pressed_keys.clone(),
-                    active_mods,
-                };
+                keyboard.pressed_keys = pressed_keys.clone();
+                keyboard.active_mods = active_mods;
             }
 
             {
@@ -143,6 +209,7 @@ impl EventHandler for Rusted {
             }
 
             if is_key_pressed(ctx, KeyCode::W) && is_key_pressed(ctx, KeyCode::LControl) {
+                self.save_game();
                 ctx.continuing = false;
             }
 
@@ -193,6 +260,8 @@ impl EventHandler for Rusted {
                 ));
                 text.add(format!("\nLoaded chunks: {}", level.loaded_chunks.len()));
                 text.add(format!("\nZoom: {}", camera.zoom));
+                let turn = world.read_resource::<Turn>();
+                text.add(format!("\nMode: {:?}  Turn: {}", turn.mode, turn.count));
                 graphics::draw(ctx, &text, DrawParam::default());
 
                 graphics::present(ctx);
@@ -212,10 +281,8 @@ impl EventHandler for Rusted {
             {
                 let mut world = &self.world;
                 let mut keyboard = world.write_resource::<Keyboard>();
-                *keyboard = Keyboard {
-                    pressed_keys: pressed_keys.clone(),
-                    active_mods,
-                };
+                keyboard.pressed_keys = pressed_keys.clone();
+                keyboard.active_mods = active_mods;
             }
 
             {
@@ -224,6 +291,7 @@ impl EventHandler for Rusted {
             }
 
             if is_key_pressed(ctx, KeyCode::W) && is_key_pressed(ctx, KeyCode::LControl) {
+                self.save_game();
                 ctx.continuing = false;
             }
 
@@ -274,6 +342,8 @@ impl EventHandler for Rusted {
                 ));
                 text.add(format!("\nLoaded chunks: {}", level.loaded_chunks.len()));
                 text.add(format!("\nZoom: {}", camera.zoom));
+                let turn = world.read_resource::<Turn>();
+                text.add(format!("\nMode: {:?}  Turn: {}", turn.mode, turn.count));
                 graphics::draw(ctx, &text, DrawParam::default());
 
                 graphics::present(ctx)?;
@@ -292,6 +362,11 @@ impl EventHandler for Rusted {
         graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width, height));
     }
 
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.save_game();
+        false
+    }
+
     fn mouse_wheel_event(&mut self, _ctx: &mut Context, dx: f32, dy: f32) {
         let mut camera = self.world.write_resource::<Camera>();
         if dy == 1.0 {
@@ -357,6 +432,27 @@ impl EventHandler for Rusted {
     }
 }
 
+/// The assets directory of the crate when run through cargo, otherwise the one next to the executable
+/// or in the working directory
+fn asset_dir() -> Option<path::PathBuf> {
+    let mut candidates = Vec::new();
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        candidates.push(path::PathBuf::from(manifest_dir).join("assets"));
+    }
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        candidates.push(exe_dir.join("assets"));
+    }
+    if let Ok(working_dir) = env::current_dir() {
+        candidates.push(working_dir.join("assets"));
+    }
+    candidates
+        .into_iter()
+        .find(|dir| dir.join(TileRegistry::FILE).is_file())
+}
+
 fn main() {
     let mut context_builder = ContextBuilder::new("Rusted", "Volcano")
         .window_setup(ggez::conf::WindowSetup::default().title("Rusty"))
@@ -366,11 +462,20 @@ fn main() {
                 .resizable(true),
         );
 
-    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let mut path = path::PathBuf::from(manifest_dir);
-        path.push("assets");
-        println!("Adding path {:?}", path);
-        context_builder = context_builder.add_resource_path(path);
+    match asset_dir() {
+        Some(path) => {
+            println!("Adding path {:?}", path);
+
+            let installed =
+                TileRegistry::load(&path.join(TileRegistry::FILE)).and_then(TileRegistry::install);
+            if let Err(e) = installed {
+                println!("Failed to load tiles: {}", e);
+                process::exit(1);
+            }
+
+            context_builder = context_builder.add_resource_path(path);
+        }
+        None => println!("No assets directory found, using the builtin tiles"),
     }
 
     let (mut ctx, event_loop) = context_builder
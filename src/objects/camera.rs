@@ -37,6 +37,19 @@ impl Camera {
         );
     }
 
+    /// First and last tile in view, both inclusive
+    pub fn tile_bounds(&self) -> (Position, Position) {
+        let end = self.pos
+            + pos!(
+                (self.width as f32 / self.zoom) as i32,
+                (self.height as f32 / self.zoom) as i32
+            );
+        return (
+            WorldPosition::to_tile(self.pos),
+            WorldPosition::to_tile(end),
+        );
+    }
+
     pub fn chunk_in_view(&self, chunk_pos: Position) -> bool {
         return Self::rect_in_view(
             self,
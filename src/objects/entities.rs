@@ -1,14 +1,31 @@
 use rand::Rng;
+use specs::saveload::MarkedBuilder;
 use specs::{Builder, Entity, World, WorldExt};
 
 use crate::objects::sprite_atlas::SpriteId;
+use crate::save::SaveMarker;
 use crate::systems::ai_system::{Ai, Target};
+use crate::systems::fov_system::Viewshed;
 use crate::systems::health_system::Health;
+use crate::systems::lighting_system::LightSource;
+use crate::systems::movement_system::Blocking;
+use crate::systems::turn_system::Energy;
+use crate::world::light::Light;
 use crate::{pos, ChunkLoader, Control, Movement, Position};
 
 pub struct Entities;
 
 impl Entities {
+    pub const PLAYER_VIEW_RANGE: i32 = 10;
+    pub const AI_VIEW_RANGE: i32 = 6;
+    /// Ais are a bit slower than the player, so they can be outrun when turn based
+    pub const AI_SPEED: i32 = 8;
+    pub const PLAYER_LANTERN: Light = Light {
+        radius: 8,
+        color: [1.0, 0.85, 0.6],
+        falloff: 1.0,
+    };
+
     pub fn create_player(world: &mut World) -> Entity {
         world
             .create_entity()
@@ -18,6 +35,11 @@ impl Entities {
             .with(Control)
             .with(ChunkLoader::default())
             .with(Health::default())
+            .with(Viewshed::new(Entities::PLAYER_VIEW_RANGE))
+            .with(LightSource(Entities::PLAYER_LANTERN))
+            .with(Blocking)
+            .with(Energy::default())
+            .marked::<SaveMarker>()
             .build()
     }
 
@@ -32,6 +54,10 @@ impl Entities {
             .with(Ai::default())
             .with(Target(target))
             .with(Health::default())
+            .with(Viewshed::new(Entities::AI_VIEW_RANGE))
+            .with(Blocking)
+            .with(Energy::new(Entities::AI_SPEED))
+            .marked::<SaveMarker>()
             .build()
     }
 }
@@ -41,7 +41,7 @@ impl GameObject {
         let d_loc = self.pos + pos!(dx, dy);
         let blocked = level
             .get_loaded_tile(d_loc)
-            .and_then(|tile| Some(tile.blocked))
+            .and_then(|tile| Some(tile.blocked()))
             .unwrap_or(true);
 
         if !blocked {
@@ -1,10 +1,11 @@
 use ggez::graphics::spritebatch::{SpriteBatch, SpriteIdx};
-use ggez::graphics::{DrawParam, Image, Rect};
+use ggez::graphics::{Color, DrawParam, Image, Rect};
 use ggez::{graphics, Context, GameResult};
+use serde::{Deserialize, Serialize};
 
 use crate::{Point2, Tile};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct SpriteId {
     pub x: usize,
     pub y: usize,
@@ -105,6 +106,18 @@ impl SpriteAtlas {
     }
 
     pub fn add(&mut self, sprite_id: &SpriteId, x: f32, y: f32, zoom: f32) -> SpriteIdx {
+        self.add_tinted(sprite_id, x, y, zoom, Color::WHITE)
+    }
+
+    /// Adds a sprite with its colours multiplied by `color`
+    pub fn add_tinted(
+        &mut self,
+        sprite_id: &SpriteId,
+        x: f32,
+        y: f32,
+        zoom: f32,
+        color: Color,
+    ) -> SpriteIdx {
         if sprite_id.x >= self.width || sprite_id.y >= self.height {
             panic!("Sprite id {} {} is out of bounds", sprite_id.x, sprite_id.y);
         }
@@ -122,7 +135,8 @@ impl SpriteAtlas {
                 y: y * zoom,
             })
             .src(src)
-            .scale([scale_factor, scale_factor]);
+            .scale([scale_factor, scale_factor])
+            .color(color);
 
         return self.batch.add(draw_params);
     }
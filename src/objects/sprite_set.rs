@@ -2,18 +2,66 @@ use crate::objects::sprite_atlas::SpriteId;
 use crate::world::tile::TileType;
 
 pub trait TileSpriteSet {
+    /// Picks the sprite of a tile from the types of its neighbours, given in `DIRECTIONS` order
     fn get_sprite_id(&self, neighbours: [TileType; 8]) -> SpriteId;
 }
 
 pub struct WallTileSpriteSet;
 
 impl TileSpriteSet for WallTileSpriteSet {
+    /// Walls are open towards neighbouring walls in the cardinal directions, the corner variant is used
+    /// when the diagonal between two open sides is not a wall.
+    /// The atlas has no sprites for walls without a north or south side open, those use the sprite
+    /// that is also open to the south.
     fn get_sprite_id(&self, neighbours: [TileType; 8]) -> SpriteId {
-        match neighbours {
-            [TileType::Wall, TileType::Wall, TileType::Wall, TileType::Wall, TileType::Wall, TileType::Wall, TileType::Wall, TileType::Wall] => {
-                SpriteId::WALL_NESW_OPEN
+        let [n, ne, e, se, s, sw, w, nw] = neighbours.map(|tile_type| tile_type == TileType::Wall);
+        let corner_open = (n && e && !ne) || (e && s && !se) || (s && w && !sw) || (w && n && !nw);
+        let corner = |open: SpriteId, open_corner: SpriteId| {
+            if corner_open {
+                open_corner
+            } else {
+                open
+            }
+        };
+
+        match (n, e, s, w) {
+            (false, false, false, false) => SpriteId::WALL_S_OPEN,
+            (false, false, true, false) => SpriteId::WALL_S_OPEN,
+            (true, false, true, false) => SpriteId::WALL_NS_OPEN,
+            (true, false, false, false) => SpriteId::WALL_N_OPEN,
+
+            (false, true, false, false) => SpriteId::WALL_ES_OPEN,
+            (false, true, true, false) => {
+                corner(SpriteId::WALL_ES_OPEN, SpriteId::WALL_ES_OPEN_CORNER)
+            }
+            (true, true, true, false) => {
+                corner(SpriteId::WALL_NES_OPEN, SpriteId::WALL_NES_OPEN_CORNER)
+            }
+            (true, true, false, false) => {
+                corner(SpriteId::WALL_NE_OPEN, SpriteId::WALL_NE_OPEN_CORNER)
+            }
+
+            (false, true, false, true) => SpriteId::WALL_ESW_OPEN,
+            (false, true, true, true) => {
+                corner(SpriteId::WALL_ESW_OPEN, SpriteId::WALL_ESW_OPEN_CORNER)
+            }
+            (true, true, true, true) => {
+                corner(SpriteId::WALL_NESW_OPEN, SpriteId::WALL_NESW_OPEN_CORNER)
+            }
+            (true, true, false, true) => {
+                corner(SpriteId::WALL_NEW_OPEN, SpriteId::WALL_NEW_OPEN_CORNER)
+            }
+
+            (false, false, false, true) => SpriteId::WALL_SW_OPEN,
+            (false, false, true, true) => {
+                corner(SpriteId::WALL_SW_OPEN, SpriteId::WALL_SW_OPEN_CORNER)
+            }
+            (true, false, true, true) => {
+                corner(SpriteId::WALL_NSW_OPEN, SpriteId::WALL_NSW_OPEN_CORNER)
+            }
+            (true, false, false, true) => {
+                corner(SpriteId::WALL_NW_OPEN, SpriteId::WALL_NW_OPEN_CORNER)
             }
-            _ => SpriteId::WALL_N_OPEN,
         }
     }
 }
@@ -0,0 +1,69 @@
+use bincode::Options;
+use specs::saveload::{DeserializeComponents, EntityData, SerializeComponents};
+use specs::world::EntitiesRes;
+use specs::{Entity, WriteStorage};
+
+use crate::save::{SaveError, SaveMarker, SaveMarkerAllocator, SaveStorages};
+
+/// Stores entities with the chunk they are in, so they are unloaded and loaded along with it.
+///
+/// Entities are bincode encoded as a sequence of `EntityData`, entity references are kept through their markers.
+pub struct ChunkEntities;
+
+impl ChunkEntities {
+    /// Serializes the components of all given entities, unmarked entities are skipped
+    pub fn serialize(
+        entity_list: &[Entity],
+        markers: &WriteStorage<SaveMarker>,
+        storages: &SaveStorages,
+    ) -> Result<Vec<u8>, SaveError> {
+        if entity_list.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids = |entity| markers.get(entity).cloned();
+        let mut entity_data = Vec::with_capacity(entity_list.len());
+        for entity in entity_list {
+            let marker = match markers.get(*entity) {
+                Some(marker) => *marker,
+                None => continue,
+            };
+            entity_data.push(EntityData {
+                marker,
+                components: SerializeComponents::<SaveError, SaveMarker>::serialize_entity(
+                    storages, *entity, ids,
+                )?,
+            });
+        }
+
+        Ok(bincode::serialize(&entity_data)?)
+    }
+
+    /// Spawns the entities serialized by `ChunkEntities::serialize`.
+    /// Entities whose marker is still alive are updated instead of spawned again.
+    pub fn deserialize(
+        data: &[u8],
+        entities: &EntitiesRes,
+        markers: &mut WriteStorage<SaveMarker>,
+        allocator: &mut SaveMarkerAllocator,
+        storages: &mut SaveStorages,
+    ) -> Result<(), SaveError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Same configuration as `bincode::serialize`
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let mut deserializer = bincode::Deserializer::from_slice(data, options);
+        DeserializeComponents::<SaveError, SaveMarker>::deserialize(
+            storages,
+            entities,
+            markers,
+            allocator,
+            &mut deserializer,
+        )?;
+        Ok(())
+    }
+}
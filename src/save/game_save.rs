@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::Path;
+
+use ron::ser::PrettyConfig;
+use ron::Options;
+use serde::{Deserialize, Serialize};
+use specs::saveload::{DeserializeComponents, SerializeComponents};
+use specs::{Entities, ReadStorage, World, WorldExt, Write, WriteStorage};
+
+use crate::save::{SaveError, SaveMarker, SaveMarkerAllocator, SaveStorages};
+use crate::world::generation::GeneratorKind;
+use crate::Level;
+
+#[derive(Serialize, Deserialize)]
+struct LevelData {
+    seed: i32,
+    #[serde(default)]
+    generator: GeneratorKind,
+    /// Markers below this are taken, including those of entities stored with unloaded chunks
+    #[serde(default)]
+    next_marker: u64,
+}
+
+/// Saves are stored in the level directory:
+/// - `level.ron` holds the level settings
+/// - `entities.ron` holds every entity marked with a `SaveMarker`
+/// - `regions/` holds the chunk data
+pub struct GameSave;
+
+impl GameSave {
+    const LEVEL_FILE: &'static str = "level.ron";
+    const ENTITIES_FILE: &'static str = "entities.ron";
+
+    pub fn save(world: &World) -> Result<(), SaveError> {
+        let mut level = world.write_resource::<Level>();
+        fs::create_dir_all(&level.dir)?;
+        level.save_chunks()?;
+
+        let level_data = LevelData {
+            seed: level.seed,
+            generator: level.generator_kind.clone(),
+            next_marker: world.read_resource::<SaveMarkerAllocator>().next_id(),
+        };
+        fs::write(
+            level.dir.join(GameSave::LEVEL_FILE),
+            ron::ser::to_string_pretty(&level_data, PrettyConfig::default())?,
+        )?;
+
+        let (entities, markers, storages) =
+            world.system_data::<(Entities, ReadStorage<SaveMarker>, SaveStorages)>();
+
+        let mut buffer = Vec::new();
+        let mut serializer =
+            ron::ser::Serializer::with_options(&mut buffer, None, Options::default())?;
+        SerializeComponents::<SaveError, SaveMarker>::serialize(
+            &storages,
+            &entities,
+            &markers,
+            &mut serializer,
+        )?;
+        fs::write(level.dir.join(GameSave::ENTITIES_FILE), buffer)?;
+
+        println!("Saved game to {:?}", level.dir);
+        Ok(())
+    }
+
+    /// Replaces the level and all entities of `world` with the save stored in `dir`.
+    /// Components have to be registered beforehand, entity references are remapped through their markers.
+    pub fn load(world: &mut World, dir: &Path) -> Result<(), SaveError> {
+        let level_data: LevelData =
+            ron::de::from_str(&fs::read_to_string(dir.join(GameSave::LEVEL_FILE))?)?;
+        let entities_data = fs::read_to_string(dir.join(GameSave::ENTITIES_FILE))?;
+
+        world.delete_all();
+        world.maintain();
+        let mut level = Level::with_dir(level_data.seed, level_data.generator, dir);
+        // Keep the event channels so readers registered by systems stay valid
+        {
+            let mut previous = world.write_resource::<Level>();
+            std::mem::swap(&mut level.tile_events, &mut previous.tile_events);
+            std::mem::swap(&mut level.chunk_events, &mut previous.chunk_events);
+        }
+        world.insert(level);
+        // Markers of entities stored with unloaded chunks are not handed out again
+        let mut allocator = SaveMarkerAllocator::new();
+        allocator.reserve(level_data.next_marker);
+        world.insert(allocator);
+
+        let (entities, mut markers, mut allocator, mut storages) = world.system_data::<(
+            Entities,
+            WriteStorage<SaveMarker>,
+            Write<SaveMarkerAllocator>,
+            SaveStorages,
+        )>();
+
+        let mut deserializer = ron::de::Deserializer::from_str(&entities_data)?;
+        DeserializeComponents::<SaveError, SaveMarker>::deserialize(
+            &mut storages,
+            &entities,
+            &mut markers,
+            &mut allocator,
+            &mut deserializer,
+        )?;
+
+        println!("Loaded game from {:?}", dir);
+        Ok(())
+    }
+}
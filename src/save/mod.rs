@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use specs::saveload::{Marker, MarkerAllocator};
+use specs::world::EntitiesRes;
+use specs::{Component, DenseVecStorage, Entity, Join, ReadStorage, WriteStorage};
+
+use crate::objects::sprite_atlas::SpriteId;
+use crate::systems::ai_system::{Ai, Target};
+use crate::systems::fov_system::Viewshed;
+use crate::systems::health_system::Health;
+use crate::systems::lighting_system::LightSource;
+use crate::systems::movement_system::Blocking;
+use crate::systems::turn_system::Energy;
+use crate::{ChunkLoader, Control, Movement, Position};
+
+pub mod chunk_entities;
+pub mod game_save;
+
+/// Marks entities that are written to save games, ids stay unique across the live world and the
+/// entities stored with unloaded chunks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SaveMarker(u64);
+
+impl Component for SaveMarker {
+    type Storage = DenseVecStorage<SaveMarker>;
+}
+
+impl Marker for SaveMarker {
+    type Identifier = u64;
+    type Allocator = SaveMarkerAllocator;
+
+    fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hands out `SaveMarker` ids and maps them to live entities.
+///
+/// Keeps track of the next id itself, entities stored with unloaded chunks are not alive so their ids
+/// would otherwise be handed out again. The next id is saved with the level and reserved again on load.
+#[derive(Debug, Default)]
+pub struct SaveMarkerAllocator {
+    next_id: u64,
+    mapping: HashMap<u64, Entity>,
+}
+
+impl SaveMarkerAllocator {
+    pub fn new() -> Self {
+        SaveMarkerAllocator::default()
+    }
+
+    /// Ids below this were handed out or seen, including those of entities stored with unloaded chunks
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Never hands out ids below `next_id`
+    pub fn reserve(&mut self, next_id: u64) {
+        self.next_id = self.next_id.max(next_id);
+    }
+}
+
+impl MarkerAllocator<SaveMarker> for SaveMarkerAllocator {
+    fn allocate(&mut self, entity: Entity, id: Option<u64>) -> SaveMarker {
+        let id = id.unwrap_or(self.next_id);
+        self.reserve(id + 1);
+        self.mapping.insert(id, entity);
+        SaveMarker(id)
+    }
+
+    fn retrieve_entity_internal(&self, id: u64) -> Option<Entity> {
+        self.mapping.get(&id).copied()
+    }
+
+    fn maintain(&mut self, entities: &EntitiesRes, storage: &ReadStorage<SaveMarker>) {
+        self.mapping = (entities, storage)
+            .join()
+            .map(|(entity, marker)| (marker.id(), entity))
+            .collect();
+    }
+}
+
+/// Storages of every persisted component, serialized in this order
+pub type SaveStorages<'a> = (
+    WriteStorage<'a, Position>,
+    WriteStorage<'a, Movement>,
+    WriteStorage<'a, Health>,
+    WriteStorage<'a, Ai>,
+    WriteStorage<'a, Target>,
+    WriteStorage<'a, ChunkLoader>,
+    WriteStorage<'a, SpriteId>,
+    WriteStorage<'a, Control>,
+    WriteStorage<'a, Viewshed>,
+    WriteStorage<'a, LightSource>,
+    WriteStorage<'a, Blocking>,
+    WriteStorage<'a, Energy>,
+);
+
+#[derive(Debug)]
+pub struct SaveError {
+    pub message: String,
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl<E: Error> From<E> for SaveError {
+    fn from(e: E) -> Self {
+        SaveError {
+            message: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::saveload::{MarkedBuilder, MarkerAllocator};
+    use specs::{Builder, World, WorldExt};
+
+    use super::{SaveMarker, SaveMarkerAllocator};
+
+    #[test]
+    fn markers_continue_after_reserved_ids() {
+        let mut world = World::new();
+        world.register::<SaveMarker>();
+        let mut allocator = SaveMarkerAllocator::new();
+        allocator.reserve(7);
+        world.insert(allocator);
+
+        // Restoring an entity stored with an unloaded chunk keeps its id and moves the next id past it
+        let restored = world.create_entity().build();
+        let marker = world
+            .write_resource::<SaveMarkerAllocator>()
+            .allocate(restored, Some(9));
+        assert_eq!(marker, SaveMarker(9));
+
+        let entity = world.create_entity().marked::<SaveMarker>().build();
+        assert_eq!(
+            world.read_storage::<SaveMarker>().get(entity),
+            Some(&SaveMarker(10))
+        );
+        assert_eq!(world.read_resource::<SaveMarkerAllocator>().next_id(), 11);
+        assert_eq!(
+            world
+                .read_resource::<SaveMarkerAllocator>()
+                .retrieve_entity_internal(10),
+            Some(entity)
+        );
+    }
+}
@@ -1,28 +1,42 @@
+use hashbrown::HashSet;
 use pathfinding::prelude::astar;
+use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
-use std::mem::MaybeUninit;
-use std::ops::Range;
 
+use specs::error::NoError;
+use specs::saveload::{ConvertSaveload, Marker};
+use specs::shrev::ReaderId;
 use specs::{
-    Component, Entity, Join, Read, ReadStorage, System, VecStorage, WorldExt, Write, WriteStorage,
+    Component, ConvertSaveload, Entities, Entity, Join, Read, ReadStorage, System, VecStorage,
+    WorldExt, Write, WriteStorage,
 };
 
-use crate::utils::matrix::matrix;
-use crate::world::chunk::LoadedChunk;
+use crate::systems::flow_field_system::FlowFields;
+use crate::systems::fov_system::Viewshed;
+use crate::systems::turn_system::{Energy, Turn};
 use crate::world::direction::DIRECTIONS;
-use crate::{pos, Level, Movement, Position, Tile, TilePosition, World};
-
+use crate::world::level::TileChanged;
+use crate::world::light::LightMap;
+use crate::world::occupancy::Occupancy;
+use crate::world::portal_graph::PortalGraph;
+use crate::world::spatial_index::SpatialIndex;
+use crate::{pos, Control, Level, Movement, Position, World};
+
+#[derive(Clone, ConvertSaveload)]
 pub struct Target(pub Entity);
 
 impl Component for Target {
     type Storage = VecStorage<Target>;
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Pathing {
     pub path: Vec<Position>,
     pub goal_tile_pos: Position,
     pub cost: i32,
     pub cur: usize,
+    /// Extensions appended by `splice` since the path was searched
+    pub splices: u32,
 }
 
 impl Pathing {
@@ -32,22 +46,99 @@ impl Pathing {
             goal_tile_pos,
             cost,
             cur: 0,
+            splices: 0,
+        }
+    }
+
+    /// Tiles of the path that have not been walked yet
+    pub fn remaining(&self) -> &[Position] {
+        &self.path[self.cur.min(self.path.len())..]
+    }
+
+    /// Follows the goal to a nearby tile without searching the whole path again.
+    ///
+    /// A goal that moved onto the rest of the path cuts it short, otherwise a short path from the previous
+    /// goal to the new one is appended. Returns false if there is no such path within `Ai::SPLICE_RANGE`,
+    /// or once the path was extended `Ai::MAX_SPLICES` times or winds more than `Ai::MAX_DETOUR` times
+    /// the distance from `current_tile_pos` to the goal, so it is searched again instead.
+    pub fn splice(
+        &mut self,
+        current_tile_pos: Position,
+        goal_tile_pos: Position,
+        level: &Level,
+        occupancy: &Occupancy,
+    ) -> bool {
+        if let Some(index) = self
+            .remaining()
+            .iter()
+            .position(|pos| *pos == goal_tile_pos)
+        {
+            self.path.truncate(self.cur + index + 1);
+            self.goal_tile_pos = goal_tile_pos;
+            return true;
+        }
+
+        if self.splices >= Ai::MAX_SPLICES
+            || self.remaining().len() as i32 > Ai::MAX_DETOUR * current_tile_pos.dist(goal_tile_pos)
+        {
+            return false;
         }
+
+        let from = self.goal_tile_pos;
+        let extension = astar(
+            &from,
+            |pos| {
+                DIRECTIONS
+                    .iter()
+                    .map(|dir| *pos + *dir)
+                    .filter(|n_pos| n_pos.dist(from) <= Ai::SPLICE_RANGE)
+                    .filter_map(|n_pos| {
+                        PortalGraph::step_cost(level, n_pos)
+                            .map(|cost| (n_pos, cost + occupancy.cost_at(n_pos)))
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |pos| pos.dist(goal_tile_pos),
+            |pos| *pos == goal_tile_pos,
+        );
+        return match extension {
+            Some((extension, cost)) => {
+                self.path.extend(extension.into_iter().skip(1));
+                self.cost += cost;
+                self.goal_tile_pos = goal_tile_pos;
+                self.splices += 1;
+                true
+            }
+            None => false,
+        };
     }
 
-    pub fn next(&mut self) -> Option<Position> {
-        if self.cur >= self.path.len() {
-            return None;
+    /// The tile to step onto from `current_tile_pos`, the path only advances once its tiles are reached
+    /// so blocked moves are tried again
+    pub fn step_from(&mut self, current_tile_pos: Position) -> Option<Position> {
+        if let Some(index) = self
+            .remaining()
+            .iter()
+            .position(|pos| *pos == current_tile_pos)
+        {
+            self.cur += index + 1;
         }
-        let pos = self.path[self.cur];
-        self.cur += 1;
-        Some(pos)
+        self.path.get(self.cur).copied()
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Ai {
     pub t: i32,
     pub pathing: Option<Pathing>,
+    /// Runs away from the target instead of chasing it
+    pub fleeing: bool,
+    /// Ticks to wait after the last failed path search, doubled by every failure in a row
+    pub repath_backoff: u32,
+    /// Ticks left until the next path search is allowed
+    pub repath_wait: u32,
+    /// Steps in a row the next tile of the path was occupied
+    pub blocked: u32,
 }
 
 impl Default for Ai {
@@ -55,6 +146,10 @@ impl Default for Ai {
         Ai {
             t: 0,
             pathing: None,
+            fleeing: false,
+            repath_backoff: 0,
+            repath_wait: 0,
+            blocked: 0,
         }
     }
 }
@@ -64,54 +159,77 @@ impl Component for Ai {
 }
 
 impl Ai {
+    /// Least brightness of a tile for the ai to notice its target on it
+    pub const MIN_BRIGHTNESS: f32 = 0.25;
+    /// How far the goal may move for the path to be spliced instead of searched again
+    pub const SPLICE_RANGE: i32 = 3;
+    /// Most extensions spliced onto a path before it is searched again
+    pub const MAX_SPLICES: u32 = 8;
+    /// How many times the distance to the goal a spliced path may be long before it is searched again
+    pub const MAX_DETOUR: i32 = 4;
+    pub const MAX_REPATH_BACKOFF: u32 = 64;
+    /// Steps in a row an ai waits for an entity to move out of its way before searching a way around it
+    pub const MAX_BLOCKED: u32 = 3;
+
     #[inline(always)]
     pub fn has_path(&self) -> bool {
         self.pathing.is_some()
     }
 
+    /// Searches a path through the portal graph, only chunks in the graph are pathed through
     pub fn find_path(
         &mut self,
         current_tile_pos: Position,
         goal_tile_pos: Position,
         level: &Level,
+        graph: &PortalGraph,
+        occupancy: &Occupancy,
     ) -> bool {
-        let current_chunk_pos = TilePosition::to_chunk(current_tile_pos);
-        let mut chunk_cache = ChunkCache::<7>::new(current_chunk_pos, &level);
-
-        let successors = |pos: &Position| {
-            let neighbours = chunk_cache.get_tile_neighbours(*pos);
-            neighbours
-                .into_iter()
-                .map(|(tile, pos)| (pos, tile.traversal_cost))
-                .collect::<Vec<(Position, i32)>>()
-        };
-
-        let heuristic = |pos: &Position| {
-            return pos.dist(goal_tile_pos);
-        };
-
-        let success = |pos: &Position| {
-            return *pos == goal_tile_pos || pos.is_adjacent(goal_tile_pos);
+        return match graph.find_path(current_tile_pos, goal_tile_pos, level, occupancy) {
+            Some((path, cost)) => {
+                self.pathing = Some(Pathing::new(path, goal_tile_pos, cost));
+                true
+            }
+            None => false,
         };
+    }
 
-        let path = astar(&current_tile_pos, successors, heuristic, success);
+    /// Searches a new path unless waiting out a backoff or the `budget` of searches is used up,
+    /// the current path is kept when no search happens or it fails
+    pub fn repath(
+        &mut self,
+        current_tile_pos: Position,
+        goal_tile_pos: Position,
+        level: &Level,
+        graph: &PortalGraph,
+        occupancy: &Occupancy,
+        budget: &mut usize,
+    ) {
+        if self.repath_wait > 0 {
+            self.repath_wait -= 1;
+            return;
+        }
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
 
-        return if path.is_some() {
-            let path = path.unwrap();
-            self.pathing = Some(Pathing::new(path.0, goal_tile_pos, path.1));
-            true
+        if self.find_path(current_tile_pos, goal_tile_pos, level, graph, occupancy) {
+            self.repath_backoff = 0;
         } else {
-            false
-        };
+            // The goal is unreachable for now, likely in a chunk that is not loaded yet
+            self.repath_backoff = (self.repath_backoff * 2).clamp(1, Ai::MAX_REPATH_BACKOFF);
+            self.repath_wait = self.repath_backoff;
+        }
     }
 
     #[inline(always)]
-    pub fn next_pos(&mut self) -> Option<Position> {
+    pub fn next_pos(&mut self, current_tile_pos: Position) -> Option<Position> {
         return if self.pathing.is_none() {
             None
         } else {
             let pathing = self.pathing.as_mut().unwrap();
-            let next = pathing.next();
+            let next = pathing.step_from(current_tile_pos);
 
             if next.is_some() {
                 next
@@ -123,37 +241,196 @@ impl Ai {
     }
 }
 
-pub struct AiSystem;
+#[derive(Default)]
+pub struct AiSystem {
+    tile_changes: Option<ReaderId<TileChanged>>,
+}
 
 impl AiSystem {
     pub const NAV_RANGE: usize = 3;
+    /// Most full path searches per tick, ais over the budget keep their current path until the next tick
+    pub const REPATH_BUDGET: usize = 4;
+    /// How far ais without a viewshed look for a new target
+    pub const ACQUIRE_RANGE: i32 = 8;
+
+    /// The nearest controlled entity the ai can see, ais without a viewshed notice any within `ACQUIRE_RANGE`
+    fn acquire_target(
+        pos: Position,
+        view: Option<&Viewshed>,
+        index: &SpatialIndex,
+        control: &ReadStorage<Control>,
+    ) -> Option<Entity> {
+        let range = view.map_or(AiSystem::ACQUIRE_RANGE, |view| view.range);
+        index
+            .in_radius(pos, range)
+            .filter(|(entity, _)| control.contains(*entity))
+            .filter(|(_, tile_pos)| view.is_none_or(|view| view.can_see(*tile_pos)))
+            .min_by_key(|(entity, tile_pos)| (tile_pos.dist(pos), entity.id()))
+            .map(|(entity, _)| entity)
+    }
 }
 
 impl<'a> System<'a> for AiSystem {
     type SystemData = (
+        Entities<'a>,
         Read<'a, Level>,
         ReadStorage<'a, Position>,
         WriteStorage<'a, Movement>,
-        ReadStorage<'a, Target>,
+        WriteStorage<'a, Target>,
         WriteStorage<'a, Ai>,
+        ReadStorage<'a, Viewshed>,
+        Read<'a, LightMap>,
+        Read<'a, FlowFields>,
+        Read<'a, PortalGraph>,
+        Read<'a, Occupancy>,
+        Read<'a, SpatialIndex>,
+        ReadStorage<'a, Control>,
+        Read<'a, Turn>,
+        ReadStorage<'a, Energy>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (level, position, mut movement, target, mut ai_type) = data;
+        let (
+            entities,
+            level,
+            position,
+            mut movement,
+            mut target,
+            mut ai_type,
+            viewshed,
+            light_map,
+            flow_fields,
+            graph,
+            occupancy,
+            index,
+            control,
+            turn,
+            energy,
+        ) = data;
 
         const nav_rad: usize = AiSystem::NAV_RANGE * 2 + 1;
 
-        for (pos, mov, target, ait) in (&position, &mut movement, &target, &mut ai_type).join() {
+        let mut changed = HashSet::<Position>::new();
+        if let Some(reader) = &mut self.tile_changes {
+            changed.extend(level.tile_events.read(reader).map(|change| change.tile_pos));
+        }
+        let mut budget = AiSystem::REPATH_BUDGET;
+
+        // Targets that are gone, deleted or unloaded with their chunk, are dropped for a new one in sight
+        let lost = (&entities, &target)
+            .join()
+            .filter(|(_, target)| !position.contains(target.0))
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in lost {
+            target.remove(entity);
+            if let Some(ai) = ai_type.get_mut(entity) {
+                ai.pathing = None;
+            }
+        }
+        let mut acquired = Vec::new();
+        for (entity, pos, _, view, _) in
+            (&entities, &position, &ai_type, viewshed.maybe(), !&target).join()
+        {
+            if let Some(found) = AiSystem::acquire_target(*pos, view, &index, &control) {
+                acquired.push((entity, found));
+            }
+        }
+        for (entity, found) in acquired {
+            target.insert(entity, Target(found)).unwrap();
+        }
+
+        for (pos, mov, target, ait, view, energy) in (
+            &position,
+            &mut movement,
+            &target,
+            &mut ai_type,
+            viewshed.maybe(),
+            energy.maybe(),
+        )
+            .join()
+        {
             mov.delta = pos!(0, 0);
-            let goal = position.get(target.0).unwrap();
+            // Waiting for their turn, paths and backoffs only advance when the ai acts
+            if !turn.can_act(energy, false) {
+                continue;
+            }
+            let goal = match position.get(target.0) {
+                Some(goal) => goal,
+                None => continue,
+            };
+
+            // Paths are only searched again when a tile still ahead on them changed
+            if let Some(pathing) = &ait.pathing {
+                if pathing.remaining().iter().any(|pos| changed.contains(pos)) {
+                    ait.pathing = None;
+                }
+            }
+
+            // Ais with a viewshed only notice targets they can see in enough light, or right next to them,
+            // otherwise they keep following their path to where the target was last noticed
+            let noticed = match view {
+                Some(view) => {
+                    pos.is_adjacent(*goal)
+                        || (view.can_see(*goal)
+                            && level.get_loaded_tile(*goal).is_some_and(|tile| {
+                                light_map.brightness_at(*goal, tile) >= Ai::MIN_BRIGHTNESS
+                            }))
+                }
+                None => true,
+            };
+
+            // Ais on the flow field of their target follow it, the rest fall back to their own path
+            if noticed {
+                let map = flow_fields.get(target.0).and_then(|field| {
+                    if ait.fleeing {
+                        field.flee.as_ref()
+                    } else {
+                        Some(&field.chase)
+                    }
+                });
+                if let Some(map) = map.filter(|map| map.contains(*pos)) {
+                    ait.pathing = None;
+                    if let Some(next_pos) = map.descend(*pos, &occupancy) {
+                        if ait.fleeing || pos.dist(*goal) >= 3 {
+                            mov.delta = next_pos - *pos;
+                        }
+                    }
+                    continue;
+                }
+            }
 
-            if !ait.has_path() || !ait.pathing.as_ref().unwrap().goal_tile_pos.eq(goal) {
-                ait.find_path(pos.clone(), *goal, &level);
+            if noticed {
+                let up_to_date = match &mut ait.pathing {
+                    Some(pathing) if pathing.goal_tile_pos == *goal => true,
+                    Some(pathing) if pathing.goal_tile_pos.dist(*goal) <= Ai::SPLICE_RANGE => {
+                        pathing.splice(*pos, *goal, &level, &occupancy)
+                    }
+                    _ => false,
+                };
+                if !up_to_date {
+                    ait.repath(pos.clone(), *goal, &level, &graph, &occupancy, &mut budget);
+                }
             }
 
-            let next_pos = ait.next_pos();
-            if next_pos.is_some() && pos.dist(*goal) >= 3 {
-                mov.delta = next_pos.unwrap() - pos.clone();
+            let next_pos = ait.next_pos(*pos);
+            if next_pos.is_some_and(|next_pos| !next_pos.is_adjacent(*pos)) {
+                // Pushed off the path, search a new one
+                ait.pathing = None;
+            } else if let Some(next_pos) = next_pos.filter(|_| pos.dist(*goal) >= 3) {
+                if occupancy.is_occupied(next_pos) {
+                    ait.blocked += 1;
+                } else {
+                    ait.blocked = 0;
+                }
+                if ait.blocked >= Ai::MAX_BLOCKED {
+                    // Whatever is in the way is not moving, search again with it in the occupancy
+                    ait.blocked = 0;
+                    let goal_tile_pos = ait.pathing.take().unwrap().goal_tile_pos;
+                    ait.repath(*pos, goal_tile_pos, &level, &graph, &occupancy, &mut budget);
+                } else {
+                    mov.delta = next_pos - *pos;
+                }
             }
         }
     }
@@ -163,81 +440,61 @@ impl<'a> System<'a> for AiSystem {
         world.register::<Movement>();
         world.register::<Target>();
         world.register::<Ai>();
+        world.register::<Viewshed>();
+        world.register::<Control>();
+        world.register::<Energy>();
+        self.tile_changes = Some(
+            world
+                .write_resource::<Level>()
+                .tile_events
+                .register_reader(),
+        );
     }
 }
 
-struct ChunkCache<'a, const R: usize> {
-    pub offset_chunk_pos: Position,
-    pub chunk_range: Range<i32>,
-    pub cache: [[(bool, bool, MaybeUninit<&'a LoadedChunk>); R]; R],
-    pub level: &'a Level,
-}
-
-impl<'a, const R: usize> ChunkCache<'a, R> {
-    pub fn new(center_chunk_pos: Position, level: &'a Level) -> Self {
-        let offset_chunk_pos =
-            center_chunk_pos - pos!(((R - R % 2) / 2) as i32, ((R - R % 2) / 2) as i32);
-        let chunk_range = 0..R as i32;
-        Self {
-            offset_chunk_pos,
-            chunk_range,
-            cache: matrix::<(bool, bool, MaybeUninit<&LoadedChunk>), R, R>(|| {
-                (false, false, MaybeUninit::<&LoadedChunk>::uninit())
-            }),
-            level,
-        }
-    }
-
-    fn get_chunk(&mut self, offset_chunk_pos: Position) -> Option<&LoadedChunk> {
-        if !self.in_range(offset_chunk_pos) {
-            return None;
-        }
-        let (cached, _, _) = self.cache[offset_chunk_pos.x as usize][offset_chunk_pos.y as usize];
-        if !cached {
-            self.cache[offset_chunk_pos.x as usize][offset_chunk_pos.y as usize].0 = true;
-            let chunk_option = self
-                .level
-                .get_loaded_chunk(offset_chunk_pos + self.offset_chunk_pos);
-            if chunk_option.is_some() {
-                self.cache[offset_chunk_pos.x as usize][offset_chunk_pos.y as usize].1 = true;
-                self.cache[offset_chunk_pos.x as usize][offset_chunk_pos.y as usize].2 =
-                    MaybeUninit::new(chunk_option.unwrap());
-            }
-        }
-
-        let (cached, exists, chunk) =
-            self.cache[offset_chunk_pos.x as usize][offset_chunk_pos.y as usize];
-        return if cached && exists {
-            unsafe { Some(chunk.assume_init()) }
-        } else {
-            None
-        };
-    }
-
-    #[inline(always)]
-    fn in_range(&self, offset_chunk_pos: Position) -> bool {
-        return self.chunk_range.contains(&offset_chunk_pos.x)
-            && self.chunk_range.contains(&offset_chunk_pos.y);
+#[cfg(test)]
+mod tests {
+    use super::{Ai, Pathing};
+    use crate::world::chunk::{Chunk, LoadedChunk};
+    use crate::world::occupancy::Occupancy;
+    use crate::{pos, Level, Position, Tile};
+
+    fn open_level() -> Level {
+        let mut level = Level::default();
+        let mut chunk = LoadedChunk::void();
+        chunk.tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        level.loaded_chunks.insert(pos!(0, 0), chunk);
+        level
     }
 
-    pub fn get_tile_neighbours(&mut self, tile_pos: Position) -> Vec<(Tile, Position)> {
-        let mut vec = Vec::new();
-
-        let off = self.offset_chunk_pos.clone();
+    #[test]
+    fn splices_fall_back_to_searching_again() {
+        let level = open_level();
+        let occupancy = Occupancy::default();
+        let from = pos!(2, 2);
 
-        for dir in DIRECTIONS {
-            let n_tile_pos = tile_pos + dir;
-            let offset_chunk_pos = TilePosition::to_chunk(n_tile_pos) - off;
-
-            let chunk = self.get_chunk(offset_chunk_pos);
-            if chunk.is_some() {
-                let chunk = chunk.unwrap();
-                let tile = chunk
-                    .get_tile_at(TilePosition::to_tile_in_chunk(n_tile_pos))
-                    .clone();
-                vec.push((tile, n_tile_pos));
-            }
+        // A goal walking away is followed up to `MAX_SPLICES` times
+        let mut pathing = Pathing::new(vec![from, pos!(3, 2)], pos!(3, 2), 1);
+        for x in 4..4 + Ai::MAX_SPLICES as i32 {
+            assert!(pathing.splice(from, pos!(x, 2), &level, &occupancy));
         }
-        vec
+        assert!(!pathing.splice(
+            from,
+            pos!(4 + Ai::MAX_SPLICES as i32, 2),
+            &level,
+            &occupancy
+        ));
+
+        // Cutting the path short is always fine
+        assert!(pathing.splice(from, pos!(5, 2), &level, &occupancy));
+
+        // A goal that circled back close to the ai leaves a path far longer than the distance to it
+        let path = (2..=12)
+            .map(|x| pos!(x, 2))
+            .chain((3..=6).map(|y| pos!(12, y)))
+            .chain((3..12).rev().map(|x| pos!(x, 6)))
+            .collect::<Vec<_>>();
+        let mut pathing = Pathing::new(path, pos!(3, 6), 23);
+        assert!(!pathing.splice(from, pos!(3, 5), &level, &occupancy));
     }
 }
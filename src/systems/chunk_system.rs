@@ -1,9 +1,20 @@
+use crate::save::chunk_entities::ChunkEntities;
+use crate::save::{SaveMarker, SaveMarkerAllocator, SaveStorages};
+use crate::world::spatial_index::SpatialIndex;
 use crate::{pos, Level, Position, TilePosition, World};
-use specs::{Component, Join, Read, ReadStorage, RunningTime, System, VecStorage, WorldExt, Write};
+use serde::{Deserialize, Serialize};
+use specs::{
+    Component, Entities, Join, Read, RunningTime, System, VecStorage, WorldExt, Write, WriteStorage,
+};
 use std::collections::HashSet;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ChunkLoader {
     pub range: i32,
+    /// Chunks stay loaded until they are further away than this, larger than `range` so walking
+    /// back and forth over a chunk border does not keep unloading and loading the same chunks
+    #[serde(default = "ChunkLoader::default_unload_range")]
+    pub unload_range: i32,
 }
 
 impl Component for ChunkLoader {
@@ -12,50 +23,111 @@ impl Component for ChunkLoader {
 
 impl Default for ChunkLoader {
     fn default() -> Self {
-        ChunkLoader { range: 1 }
+        ChunkLoader {
+            range: 1,
+            unload_range: ChunkLoader::default_unload_range(),
+        }
+    }
+}
+
+impl ChunkLoader {
+    fn default_unload_range() -> i32 {
+        2
     }
 }
 
 pub struct ChunkSystem;
 impl<'a> System<'a> for ChunkSystem {
     type SystemData = (
+        Entities<'a>,
         Write<'a, Level>,
-        ReadStorage<'a, Position>,
-        ReadStorage<'a, ChunkLoader>,
+        WriteStorage<'a, SaveMarker>,
+        Write<'a, SaveMarkerAllocator>,
+        Read<'a, SpatialIndex>,
+        SaveStorages<'a>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut level, position, chunk_loader) = data;
+        let (entities, mut level, mut markers, mut allocator, index, mut storages) = data;
 
         let mut ensure_loaded = HashSet::<Position>::new();
-        for (pos, loader) in (&position, &chunk_loader).join() {
+        let mut keep_loaded = HashSet::<Position>::new();
+        // Positions and chunk loaders are part of the saved storages
+        for (pos, loader) in (&storages.0, &storages.5).join() {
             let loader_chunk_pos = TilePosition::to_chunk(*pos);
 
+            let unload_range = loader.unload_range.max(loader.range);
+            for x in (-unload_range)..(unload_range + 1) {
+                for y in (-unload_range)..(unload_range + 1) {
+                    keep_loaded.insert(loader_chunk_pos + pos!(x, y));
+                }
+            }
+
             for x in (-loader.range)..(loader.range + 1) {
                 for y in (-loader.range)..(loader.range + 1) {
                     let chunk_pos = loader_chunk_pos + pos!(x, y);
                     if ensure_loaded.insert(chunk_pos) {
-                        level.ensure_loaded(chunk_pos);
+                        if let Err(e) = level.request_chunk(chunk_pos) {
+                            println!("Failed to load chunk {:?}: {}", chunk_pos, e);
+                        }
                     }
                 }
             }
         }
 
+        level.install_generated_chunks();
+
+        for (chunk_pos, data) in level.take_restored_entities() {
+            if let Err(e) = ChunkEntities::deserialize(
+                &data,
+                &entities,
+                &mut markers,
+                &mut allocator,
+                &mut storages,
+            ) {
+                println!("Failed to restore entities of chunk {:?}: {}", chunk_pos, e);
+            }
+        }
+
         // Unload chunks
         let mut to_unload = Vec::new();
         for (pos, _) in &level.loaded_chunks {
-            if !ensure_loaded.contains(pos) {
+            if !keep_loaded.contains(pos) {
                 to_unload.push(pos.clone());
             }
         }
 
         for pos in to_unload {
-            level.unload_chunk(pos);
+            // Chunk loaders stay in the world, they would never be loaded again otherwise
+            let chunk_entities = index
+                .in_chunk(pos)
+                .iter()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| !storages.5.contains(*entity) && markers.contains(*entity))
+                .collect::<Vec<_>>();
+
+            let data = match ChunkEntities::serialize(&chunk_entities, &markers, &storages) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("Failed to save entities of chunk {:?}: {}", pos, e);
+                    continue;
+                }
+            };
+
+            level.unload_chunk(pos, data);
+            for entity in chunk_entities {
+                entities.delete(entity).unwrap();
+            }
+        }
+
+        if let Err(e) = level.evict_hot_chunks() {
+            println!("Failed to save evicted chunks: {}", e);
         }
     }
 
     fn setup(&mut self, world: &mut World) {
         world.register::<Position>();
         world.register::<ChunkLoader>();
+        world.register::<SaveMarker>();
     }
 }
@@ -1,17 +1,22 @@
 use crate::systems::movement_system::Movement;
+use crate::systems::turn_system::{Action, Energy, Turn};
 use crate::{Level, Position, World};
 use ggez::event::{KeyCode, KeyMods};
 use ggez::input::keyboard::is_key_pressed;
 use ggez::winit::event::VirtualKeyCode;
 use ggez::Context;
+use serde::{Deserialize, Serialize};
 use specs::{
     AccessorCow, Component, Join, NullStorage, Read, ReadExpect, ReadStorage, RunningTime, System,
-    VecStorage, WorldExt, WriteStorage,
+    VecStorage, WorldExt, Write, WriteStorage,
 };
 use std::collections::HashSet;
 
 pub struct Keyboard {
     pub pressed_keys: HashSet<VirtualKeyCode>,
+    /// Keys pressed or repeated since the controlled entity last got to act, turn based controls act
+    /// once per press. Cleared by the `ControlSystem` once consumed
+    pub just_pressed: HashSet<VirtualKeyCode>,
     pub active_mods: KeyMods,
 }
 
@@ -28,17 +33,23 @@ impl Keyboard {
         }
         return false;
     }
+
+    pub fn any_just_pressed(&self, codes: &[VirtualKeyCode]) -> bool {
+        codes.iter().any(|code| self.just_pressed.contains(code))
+    }
 }
 
 impl Default for Keyboard {
     fn default() -> Self {
         Keyboard {
             pressed_keys: HashSet::new(),
+            just_pressed: HashSet::new(),
             active_mods: KeyMods::empty(),
         }
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Control;
 
 impl Default for Control {
@@ -51,25 +62,43 @@ impl Component for Control {
     type Storage = NullStorage<Self>;
 }
 
+/// Moves the controlled entity while direction keys are held, or once per press when turn based.
+/// Waiting only takes a turn when turn based, presses made during the turn of the world are kept for
+/// the next turn of the controlled entity.
 pub struct ControlSystem;
+
+impl ControlSystem {
+    pub const WAIT_KEYS: [VirtualKeyCode; 2] = [KeyCode::Numpad5, KeyCode::Space];
+}
+
 impl<'a> System<'a> for ControlSystem {
     type SystemData = (
-        Read<'a, Keyboard>,
+        Write<'a, Keyboard>,
         WriteStorage<'a, Movement>,
         ReadStorage<'a, Control>,
+        Read<'a, Turn>,
+        WriteStorage<'a, Energy>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (keyboard, mut movements, control) = data;
+        let (mut keyboard, mut movements, control, turn, mut energy) = data;
 
-        let dx = if keyboard.any_pressed(&[
+        let pressed = |codes: &[VirtualKeyCode]| {
+            if turn.is_turn_based() {
+                keyboard.any_just_pressed(codes)
+            } else {
+                keyboard.any_pressed(codes)
+            }
+        };
+
+        let dx = if pressed(&[
             KeyCode::Numpad9,
             KeyCode::Numpad6,
             KeyCode::Numpad3,
             KeyCode::Right,
         ]) {
             1
-        } else if keyboard.any_pressed(&[
+        } else if pressed(&[
             KeyCode::Numpad7,
             KeyCode::Numpad4,
             KeyCode::Numpad1,
@@ -80,14 +109,14 @@ impl<'a> System<'a> for ControlSystem {
             0
         };
 
-        let dy = if keyboard.any_pressed(&[
+        let dy = if pressed(&[
             KeyCode::Numpad7,
             KeyCode::Numpad8,
             KeyCode::Numpad9,
             KeyCode::Up,
         ]) {
             -1
-        } else if keyboard.any_pressed(&[
+        } else if pressed(&[
             KeyCode::Numpad1,
             KeyCode::Numpad2,
             KeyCode::Numpad3,
@@ -98,14 +127,32 @@ impl<'a> System<'a> for ControlSystem {
             0
         };
 
-        for (mov, _) in (&mut movements, &control).join() {
+        let wait = turn.is_turn_based() && pressed(&ControlSystem::WAIT_KEYS);
+
+        let mut acted = false;
+        for (mov, _, energy) in (&mut movements, &control, (&mut energy).maybe()).join() {
+            mov.delta = Position::default();
+            if !turn.can_act(energy.as_deref(), true) {
+                continue;
+            }
+            acted = true;
+            if wait {
+                // Moves cost energy once made, waiting is not a move
+                energy.unwrap().spend(Action::Wait);
+                continue;
+            }
             mov.delta.x = dx;
             mov.delta.y = dy;
         }
+
+        if acted || !turn.is_turn_based() {
+            keyboard.just_pressed.clear();
+        }
     }
 
     fn setup(&mut self, world: &mut World) {
         world.register::<Movement>();
         world.register::<Control>();
+        world.register::<Energy>();
     }
 }
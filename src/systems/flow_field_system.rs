@@ -0,0 +1,126 @@
+use hashbrown::HashMap;
+use specs::shrev::ReaderId;
+use specs::{Entity, Join, Read, ReadStorage, System, World, WorldExt, Write};
+
+use crate::systems::ai_system::{Ai, Target};
+use crate::world::chunk::Chunk;
+use crate::world::dijkstra_map::DijkstraMap;
+use crate::world::level::{ChunkEvent, TileChanged};
+use crate::world::position::ChunkPosition;
+use crate::{pos, Level, Position};
+
+/// Dijkstra maps around a targeted entity, shared by every ai targeting it
+pub struct FlowField {
+    /// Position of the target when the maps were calculated
+    pub goal: Position,
+    pub chase: DijkstraMap,
+    /// Derived from `chase` once an ai targeting the entity flees
+    pub flee: Option<DijkstraMap>,
+}
+
+#[derive(Default)]
+pub struct FlowFields {
+    pub fields: HashMap<Entity, FlowField>,
+}
+
+impl FlowFields {
+    /// Distance from the target covered by its flow field
+    pub const RANGE: i32 = 48;
+
+    pub fn get(&self, target: Entity) -> Option<&FlowField> {
+        self.fields.get(&target)
+    }
+}
+
+/// Keeps a flow field for every targeted entity, recalculated when the target moves or the tiles or
+/// loaded chunks around it change
+#[derive(Default)]
+pub struct FlowFieldSystem {
+    tile_changes: Option<ReaderId<TileChanged>>,
+    chunk_changes: Option<ReaderId<ChunkEvent>>,
+}
+
+impl<'a> System<'a> for FlowFieldSystem {
+    type SystemData = (
+        Read<'a, Level>,
+        Write<'a, FlowFields>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Target>,
+        ReadStorage<'a, Ai>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (level, mut flow_fields, position, target, ai) = data;
+
+        let mut changed = Vec::<Position>::new();
+        if let Some(reader) = &mut self.tile_changes {
+            changed.extend(level.tile_events.read(reader).map(|change| change.tile_pos));
+        }
+        // Chunks are treated as a change of their center tile, padded by half a chunk when checking range
+        let mut changed_chunks = Vec::<Position>::new();
+        if let Some(reader) = &mut self.chunk_changes {
+            changed_chunks.extend(level.chunk_events.read(reader).map(|event| match event {
+                ChunkEvent::Loaded(chunk_pos) | ChunkEvent::Unloaded(chunk_pos) => {
+                    ChunkPosition::to_tile(*chunk_pos) + pos!(Chunk::SIZE / 2, Chunk::SIZE / 2)
+                }
+            }));
+        }
+
+        // Targets along with whether any ai targeting them flees
+        let mut targets = HashMap::<Entity, bool>::new();
+        for (target, ai) in (&target, &ai).join() {
+            *targets.entry(target.0).or_insert(false) |= ai.fleeing;
+        }
+        flow_fields
+            .fields
+            .retain(|entity, _| targets.contains_key(entity));
+
+        for (entity, fleeing) in targets {
+            let goal = match position.get(entity) {
+                Some(goal) => *goal,
+                None => {
+                    flow_fields.fields.remove(&entity);
+                    continue;
+                }
+            };
+
+            let stale = match flow_fields.fields.get(&entity) {
+                Some(field) => {
+                    field.goal != goal
+                        || changed
+                            .iter()
+                            .any(|tile_pos| field.chase.in_range(*tile_pos))
+                        || changed_chunks.iter().any(|tile_pos| {
+                            tile_pos.dist(field.chase.center) <= field.chase.range + Chunk::SIZE / 2
+                        })
+                }
+                None => true,
+            };
+            if stale {
+                flow_fields.fields.insert(
+                    entity,
+                    FlowField {
+                        goal,
+                        chase: DijkstraMap::new(&[goal], goal, FlowFields::RANGE, &level),
+                        flee: None,
+                    },
+                );
+            }
+
+            let field = flow_fields.fields.get_mut(&entity).unwrap();
+            if fleeing && field.flee.is_none() {
+                field.flee = Some(field.chase.flee(&level));
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<Position>();
+        world.register::<Target>();
+        world.register::<Ai>();
+        world.insert(FlowFields::default());
+        let mut level = world.write_resource::<Level>();
+        self.tile_changes = Some(level.tile_events.register_reader());
+        self.chunk_changes = Some(level.chunk_events.register_reader());
+    }
+}
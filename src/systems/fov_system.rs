@@ -0,0 +1,196 @@
+use hashbrown::HashSet;
+use serde::{Deserialize, Serialize};
+use specs::shrev::ReaderId;
+use specs::{
+    Component, Join, ReadStorage, System, VecStorage, World, WorldExt, Write, WriteStorage,
+};
+
+use crate::world::chunk::Chunk;
+use crate::world::fov::Fov;
+use crate::world::level::{ChunkEvent, TileChanged};
+use crate::world::position::ChunkPosition;
+use crate::{pos, Control, Level, Position};
+
+/// Tiles an entity can currently see
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Viewshed {
+    pub range: i32,
+    #[serde(skip)]
+    pub visible: HashSet<Position>,
+    /// Position the visible tiles were calculated from, `None` when they have to be recalculated
+    #[serde(skip)]
+    pub origin: Option<Position>,
+}
+
+impl Component for Viewshed {
+    type Storage = VecStorage<Viewshed>;
+}
+
+impl Viewshed {
+    pub fn new(range: i32) -> Self {
+        Viewshed {
+            range,
+            visible: HashSet::new(),
+            origin: None,
+        }
+    }
+
+    pub fn can_see(&self, tile_pos: Position) -> bool {
+        self.visible.contains(&tile_pos)
+    }
+}
+
+/// Recalculates viewsheds of entities that moved, saw a tile change or had a chunk load in range.
+///
+/// What the player sees is explored, tiles that change while explored but out of their sight
+/// keep their previous appearance in the fog of war.
+#[derive(Default)]
+pub struct FovSystem {
+    tile_changes: Option<ReaderId<TileChanged>>,
+    chunk_changes: Option<ReaderId<ChunkEvent>>,
+}
+
+impl<'a> System<'a> for FovSystem {
+    type SystemData = (
+        Write<'a, Level>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Viewshed>,
+        ReadStorage<'a, Control>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut level, position, mut viewshed, control) = data;
+
+        if let Some(reader) = &mut self.tile_changes {
+            let changes = level.tile_events.read(reader).copied().collect::<Vec<_>>();
+            for change in changes {
+                for (pos, view) in (&position, &mut viewshed).join() {
+                    if pos.dist(change.tile_pos) <= view.range {
+                        view.origin = None;
+                    }
+                }
+                for (view, _) in (&viewshed, &control).join() {
+                    if !view.can_see(change.tile_pos) {
+                        level.remember(change.tile_pos, change.previous);
+                    }
+                }
+            }
+        }
+
+        // Chunks that are not loaded yet block sight, viewsheds reaching into them are recalculated
+        // once they load. Loaded chunks are checked by their center tile, padded by half a chunk.
+        if let Some(reader) = &mut self.chunk_changes {
+            for event in level.chunk_events.read(reader) {
+                if let ChunkEvent::Loaded(chunk_pos) = event {
+                    let center =
+                        ChunkPosition::to_tile(*chunk_pos) + pos!(Chunk::SIZE / 2, Chunk::SIZE / 2);
+                    for (pos, view) in (&position, &mut viewshed).join() {
+                        if pos.dist(center) <= view.range + Chunk::SIZE / 2 {
+                            view.origin = None;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (pos, view) in (&position, &mut viewshed).join() {
+            if view.origin == Some(*pos) {
+                continue;
+            }
+
+            view.visible = Fov::compute(*pos, view.range, |tile_pos| {
+                match level.get_loaded_tile(tile_pos) {
+                    Some(tile) => tile.block_sight(),
+                    None => true,
+                }
+            });
+            view.origin = Some(*pos);
+        }
+
+        for (view, _) in (&viewshed, &control).join() {
+            level.explore(&view.visible);
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<Position>();
+        world.register::<Viewshed>();
+        world.register::<Control>();
+        let mut level = world.write_resource::<Level>();
+        self.tile_changes = Some(level.tile_events.register_reader());
+        self.chunk_changes = Some(level.chunk_events.register_reader());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{Builder, RunNow, System, World, WorldExt};
+
+    use super::{FovSystem, Viewshed};
+    use crate::world::chunk::{Chunk, LoadedChunk};
+    use crate::world::level::ChunkEvent;
+    use crate::{pos, Level, Position, Tile};
+
+    fn grass_chunk(chunk_pos: Position) -> LoadedChunk {
+        let mut chunk = LoadedChunk::void();
+        chunk.pos = chunk_pos;
+        chunk.tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        chunk
+    }
+
+    #[test]
+    fn viewsheds_are_recalculated_when_chunks_in_range_load() {
+        let mut level = Level::default();
+        level
+            .loaded_chunks
+            .insert(pos!(0, 0), grass_chunk(pos!(0, 0)));
+        let mut world = World::new();
+        world.insert(level);
+        let mut system = FovSystem::default();
+        System::setup(&mut system, &mut world);
+
+        let entity = world
+            .create_entity()
+            .with(pos!(Chunk::SIZE - 3, 5))
+            .with(Viewshed::new(8))
+            .build();
+        system.run_now(&world);
+        let beyond = pos!(Chunk::SIZE + 2, 5);
+        assert!(!world
+            .read_storage::<Viewshed>()
+            .get(entity)
+            .unwrap()
+            .can_see(beyond));
+        // Unloaded chunks in range do not keep the viewshed dirty
+        let origin = world.read_storage::<Viewshed>().get(entity).unwrap().origin;
+        assert_eq!(origin, Some(pos!(Chunk::SIZE - 3, 5)));
+
+        // A chunk loading far away leaves the viewshed alone
+        {
+            let mut level = world.write_resource::<Level>();
+            level
+                .loaded_chunks
+                .insert(pos!(1, 0), grass_chunk(pos!(1, 0)));
+            level
+                .chunk_events
+                .single_write(ChunkEvent::Loaded(pos!(5, 5)));
+        }
+        system.run_now(&world);
+        assert!(!world
+            .read_storage::<Viewshed>()
+            .get(entity)
+            .unwrap()
+            .can_see(beyond));
+
+        world
+            .write_resource::<Level>()
+            .chunk_events
+            .single_write(ChunkEvent::Loaded(pos!(1, 0)));
+        system.run_now(&world);
+        assert!(world
+            .read_storage::<Viewshed>()
+            .get(entity)
+            .unwrap()
+            .can_see(beyond));
+    }
+}
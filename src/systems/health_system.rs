@@ -1,7 +1,9 @@
+use serde::{Deserialize, Serialize};
 use specs::{
     Component, Entities, Entity, Join, LazyUpdate, Read, ReadStorage, System, World, WorldExt,
 };
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Health {
     pub health: i32,
 }
@@ -0,0 +1,236 @@
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use specs::shrev::ReaderId;
+use specs::{
+    Component, Entities, Entity, Join, Read, ReadStorage, System, VecStorage, World, WorldExt,
+    Write,
+};
+
+use crate::world::chunk::{LoadedChunk, CHUNK_SIZE};
+use crate::world::level::{ChunkEvent, TileChanged};
+use crate::world::light::{Light, LightMap};
+use crate::world::position::ChunkPosition;
+use crate::{pos, Level, Position, TilePosition};
+
+/// Light carried by an entity, such as the player's lantern
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LightSource(pub Light);
+
+impl Component for LightSource {
+    type Storage = VecStorage<LightSource>;
+}
+
+/// Tiles lit by one light, kept until the light moves or tiles around it change
+struct LitArea {
+    origin: Position,
+    light: Light,
+    /// `None` when the lit tiles have to be recalculated
+    lit_tiles: Option<Vec<(Position, [f32; 3])>>,
+}
+
+impl LitArea {
+    fn new(origin: Position, light: Light) -> Self {
+        LitArea {
+            origin,
+            light,
+            lit_tiles: None,
+        }
+    }
+
+    /// Whether the light may reach within `padding` tiles of a tile
+    fn reaches(&self, tile_pos: Position, padding: i32) -> bool {
+        tile_pos.dist(self.origin) <= self.light.radius + padding
+    }
+}
+
+/// Fills the `LightMap` from light sources and glowing tiles of the loaded chunks.
+///
+/// The tiles lit by each light are recalculated only when it moves, or when a tile it may reach
+/// changes or a chunk it may reach is loaded or unloaded.
+#[derive(Default)]
+pub struct LightingSystem {
+    tile_changes: Option<ReaderId<TileChanged>>,
+    chunk_changes: Option<ReaderId<ChunkEvent>>,
+    /// Glowing tiles of each loaded chunk
+    emitters: HashMap<Position, Vec<LitArea>>,
+    sources: HashMap<Entity, LitArea>,
+}
+
+impl LightingSystem {
+    fn find_emitters(chunk: &LoadedChunk) -> Vec<LitArea> {
+        let mut emitters = Vec::new();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                if let Some(light) = chunk.get_tile(x, y).light() {
+                    emitters.push(LitArea::new(
+                        ChunkPosition::to_tile(chunk.pos) + pos!(x, y),
+                        light,
+                    ));
+                }
+            }
+        }
+        emitters
+    }
+}
+
+impl<'a> System<'a> for LightingSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Level>,
+        Write<'a, LightMap>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, LightSource>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, level, mut light_map, position, light_source) = data;
+
+        // Changed tiles along with how far a light may be from them to be affected, chunks are
+        // treated as a change of their center tile padded by half a chunk
+        let mut changed = Vec::<(Position, i32)>::new();
+        if let Some(reader) = &mut self.tile_changes {
+            for change in level.tile_events.read(reader) {
+                changed.push((change.tile_pos, 0));
+                // Chunks with changed tiles are searched for glowing tiles again
+                self.emitters
+                    .remove(&TilePosition::to_chunk(change.tile_pos));
+            }
+        }
+        if let Some(reader) = &mut self.chunk_changes {
+            for event in level.chunk_events.read(reader) {
+                let chunk_pos = match event {
+                    ChunkEvent::Loaded(chunk_pos) | ChunkEvent::Unloaded(chunk_pos) => *chunk_pos,
+                };
+                changed.push((
+                    ChunkPosition::to_tile(chunk_pos) + pos!(CHUNK_SIZE / 2, CHUNK_SIZE / 2),
+                    CHUNK_SIZE / 2,
+                ));
+            }
+        }
+        for area in self
+            .sources
+            .values_mut()
+            .chain(self.emitters.values_mut().flatten())
+        {
+            if changed
+                .iter()
+                .any(|(tile_pos, padding)| area.reaches(*tile_pos, *padding))
+            {
+                area.lit_tiles = None;
+            }
+        }
+
+        self.emitters
+            .retain(|chunk_pos, _| level.loaded_chunks.contains_key(chunk_pos));
+        for (chunk_pos, chunk) in &level.loaded_chunks {
+            if !self.emitters.contains_key(chunk_pos) {
+                self.emitters
+                    .insert(*chunk_pos, LightingSystem::find_emitters(chunk));
+            }
+        }
+
+        self.sources
+            .retain(|entity, _| light_source.contains(*entity));
+        for (entity, pos, source) in (&entities, &position, &light_source).join() {
+            let moved = match self.sources.get(&entity) {
+                Some(area) => area.origin != *pos || area.light != source.0,
+                None => true,
+            };
+            if moved {
+                self.sources.insert(entity, LitArea::new(*pos, source.0));
+            }
+        }
+
+        // Chunks that are not loaded block light
+        let is_opaque = |tile_pos: Position| match level.get_loaded_tile(tile_pos) {
+            Some(tile) => tile.block_sight(),
+            None => true,
+        };
+
+        light_map.clear();
+        for area in self
+            .sources
+            .values_mut()
+            .chain(self.emitters.values_mut().flatten())
+        {
+            let lit_tiles = area
+                .lit_tiles
+                .get_or_insert_with(|| LightMap::lit_tiles(area.origin, &area.light, is_opaque));
+            light_map.add(lit_tiles);
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<Position>();
+        world.register::<LightSource>();
+        world.insert(LightMap::default());
+        let mut level = world.write_resource::<Level>();
+        self.tile_changes = Some(level.tile_events.register_reader());
+        self.chunk_changes = Some(level.chunk_events.register_reader());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{Builder, RunNow, System, World, WorldExt};
+
+    use super::{LightSource, LightingSystem};
+    use crate::world::level::tests::hand_built_level;
+    use crate::world::level::TileChanged;
+    use crate::world::light::{Light, LightMap};
+    use crate::{pos, Level, Position, Tile, TilePosition};
+
+    fn is_lit(world: &World, tile_pos: Position) -> bool {
+        let light_map = world.read_resource::<LightMap>();
+        light_map.light_at(tile_pos, &Tile::grass()) != light_map.daylight
+    }
+
+    #[test]
+    fn lit_areas_follow_sources_and_tile_changes() {
+        let mut world = World::new();
+        world.insert(hand_built_level(&[pos!(0, 0)], &[]));
+        let mut system = LightingSystem::default();
+        System::setup(&mut system, &mut world);
+
+        let lantern = Light {
+            radius: 4,
+            color: [0.5, 0.4, 0.2],
+            falloff: 1.0,
+        };
+        let entity = world
+            .create_entity()
+            .with(pos!(5, 5))
+            .with(LightSource(lantern))
+            .build();
+        system.run_now(&world);
+        assert!(is_lit(&world, pos!(7, 5)));
+        assert!(!is_lit(&world, pos!(12, 5)));
+
+        // A wall placed next to the light casts a shadow once its change is seen
+        {
+            let mut level = world.write_resource::<Level>();
+            let wall_pos = pos!(6, 5);
+            let chunk = level.loaded_chunks.get_mut(&pos!(0, 0)).unwrap();
+            chunk.set_tile_at(
+                TilePosition::to_tile_in_chunk(wall_pos),
+                Tile::wall([false; 8]),
+            );
+            level.tile_events.single_write(TileChanged {
+                tile_pos: wall_pos,
+                previous: Tile::grass(),
+                tile: Tile::wall([false; 8]),
+            });
+        }
+        system.run_now(&world);
+        assert!(is_lit(&world, pos!(6, 5)));
+        assert!(!is_lit(&world, pos!(7, 5)));
+
+        world
+            .write_storage::<Position>()
+            .insert(entity, pos!(10, 5))
+            .unwrap();
+        system.run_now(&world);
+        assert!(is_lit(&world, pos!(12, 5)));
+        assert!(!is_lit(&world, pos!(4, 5)));
+    }
+}
@@ -1,6 +1,12 @@
 pub mod ai_system;
 pub mod chunk_system;
 pub mod control_system;
+pub mod flow_field_system;
+pub mod fov_system;
 pub mod health_system;
+pub mod lighting_system;
 pub mod movement_system;
+pub mod portal_graph_system;
 pub mod render_system;
+pub mod spatial_index_system;
+pub mod turn_system;
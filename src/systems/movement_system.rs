@@ -1,10 +1,15 @@
-use crate::{Level, Position, World};
+use crate::systems::health_system::Health;
+use crate::systems::turn_system::{Action, Energy, Turn};
+use crate::world::occupancy::Occupancy;
+use crate::{Control, Level, Position, World};
 use ggez::input::mouse::position;
+use serde::{Deserialize, Serialize};
 use specs::{
-    AccessorCow, Component, Join, Read, ReadStorage, RunningTime, System, VecStorage, WorldExt,
-    WriteStorage,
+    AccessorCow, Component, Entities, Join, NullStorage, Read, ReadStorage, RunningTime, System,
+    VecStorage, WorldExt, Write, WriteStorage,
 };
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Movement {
     pub delta: Position,
 }
@@ -21,22 +26,134 @@ impl Default for Movement {
     }
 }
 
+/// Entities that take up their tile, other blocking entities can not move onto it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Blocking;
+
+impl Default for Blocking {
+    fn default() -> Self {
+        Blocking
+    }
+}
+
+impl Component for Blocking {
+    type Storage = NullStorage<Self>;
+}
+
+/// Moves entities by their movement delta, blocking entities also keep the `Occupancy` up to date.
+///
+/// When blocking entities want the same tile, the controlled entity goes first, then the lowest entity id.
+/// Entities moving onto a tile that is being left in the same tick wait for it to be left.
+///
+/// When turn based only entities whose turn it is move, and they pay for what they did. The controlled
+/// entity attacks blocking entities with health it moves into, and loses no turn walking into walls,
+/// everyone else that did not move waits.
 pub struct MovementSystem;
 impl<'a> System<'a> for MovementSystem {
     type SystemData = (
+        Entities<'a>,
         Read<'a, Level>,
         ReadStorage<'a, Movement>,
         WriteStorage<'a, Position>,
+        ReadStorage<'a, Blocking>,
+        ReadStorage<'a, Control>,
+        Write<'a, Occupancy>,
+        Read<'a, Turn>,
+        WriteStorage<'a, Energy>,
+        WriteStorage<'a, Health>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (level, movements, mut positions) = data;
-        for (mov, pos) in (&movements, &mut positions).join() {
-            let tile_optional = level.get_loaded_tile(*pos + mov.delta);
-            let blocked = tile_optional.map(|t| t.blocked).unwrap_or(true);
-            if !blocked {
-                pos.x += mov.delta.x;
-                pos.y += mov.delta.y;
+        let (
+            entities,
+            level,
+            movements,
+            mut positions,
+            blocking,
+            control,
+            mut occupancy,
+            turn,
+            mut energy,
+            mut health,
+        ) = data;
+
+        // Rebuilt every tick so spawned, deleted and unloaded entities never linger in it
+        occupancy.clear();
+        for (entity, pos, _) in (&entities, &positions, &blocking).join() {
+            occupancy.occupy(*pos, entity);
+        }
+
+        let mut pending = Vec::new();
+        // Entities acting this turn with where they started and what they did, when turn based
+        let mut acting = Vec::new();
+        for (entity, mov, pos, blocks, controlled) in (
+            &entities,
+            &movements,
+            &mut positions,
+            blocking.maybe(),
+            control.maybe(),
+        )
+            .join()
+        {
+            if !turn.can_act(energy.get(entity), controlled.is_some()) {
+                continue;
+            }
+            if turn.is_turn_based() {
+                let attacked = match occupancy.occupant(*pos + mov.delta) {
+                    Some(occupant)
+                        if controlled.is_some() && blocks.is_some() && occupant != entity =>
+                    {
+                        health
+                            .get_mut(occupant)
+                            .map(|health| health.health -= 1)
+                            .is_some()
+                    }
+                    _ => false,
+                };
+                acting.push((entity, *pos, controlled.is_some(), attacked));
+                if attacked {
+                    continue;
+                }
+            }
+            if mov.delta == Position::default() || level.is_blocked(*pos + mov.delta) {
+                continue;
+            }
+            if blocks.is_some() {
+                pending.push((controlled.is_none(), entity.id(), entity, *pos + mov.delta));
+            } else {
+                *pos = *pos + mov.delta;
+            }
+        }
+        pending.sort_by_key(|(uncontrolled, id, _, _)| (*uncontrolled, *id));
+
+        // Passes repeat while someone moved, so queues of entities follow each other in one tick
+        let mut moved = true;
+        while moved {
+            moved = false;
+            pending.retain(|(_, _, entity, to)| {
+                let pos = positions.get_mut(*entity).unwrap();
+                if occupancy.move_entity(*entity, *pos, *to) {
+                    *pos = *to;
+                    moved = true;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        for (entity, from, controlled, attacked) in acting {
+            let action = if attacked {
+                Action::Attack
+            } else if positions.get(entity) != Some(&from) {
+                Action::Move
+            } else if controlled {
+                continue;
+            } else {
+                Action::Wait
+            };
+            if let Some(energy) = energy.get_mut(entity) {
+                energy.spend(action);
             }
         }
     }
@@ -44,5 +161,61 @@ impl<'a> System<'a> for MovementSystem {
     fn setup(&mut self, world: &mut World) {
         world.register::<Movement>();
         world.register::<Position>();
+        world.register::<Blocking>();
+        world.register::<Control>();
+        world.register::<Energy>();
+        world.register::<Health>();
+        world.insert(Occupancy::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{Builder, Entity, RunNow, System, World, WorldExt};
+
+    use super::{Blocking, Movement, MovementSystem};
+    use crate::systems::turn_system::Turn;
+    use crate::world::level::tests::hand_built_level;
+    use crate::{pos, Control, Position};
+
+    fn mover(world: &mut World, from: Position, to: Position, controlled: bool) -> Entity {
+        let builder = world
+            .create_entity()
+            .with(from)
+            .with(Movement { delta: to - from })
+            .with(Blocking);
+        if controlled {
+            builder.with(Control).build()
+        } else {
+            builder.build()
+        }
+    }
+
+    fn position(world: &World, entity: Entity) -> Position {
+        *world.read_storage::<Position>().get(entity).unwrap()
+    }
+
+    #[test]
+    fn conflicts_go_to_the_controlled_entity_then_the_lowest_id() {
+        let mut world = World::new();
+        world.insert(hand_built_level(&[pos!(0, 0)], &[]));
+        world.insert(Turn::default());
+        let mut system = MovementSystem;
+        System::setup(&mut system, &mut world);
+
+        let target = pos!(5, 5);
+        let first = mover(&mut world, pos!(4, 5), target, false);
+        let second = mover(&mut world, pos!(6, 5), target, false);
+        system.run_now(&world);
+        assert_eq!(position(&world, first), target);
+        assert_eq!(position(&world, second), pos!(6, 5));
+
+        let target = pos!(10, 10);
+        let uncontrolled = mover(&mut world, pos!(9, 10), target, false);
+        let controlled = mover(&mut world, pos!(11, 10), target, true);
+        assert!(uncontrolled.id() < controlled.id());
+        system.run_now(&world);
+        assert_eq!(position(&world, controlled), target);
+        assert_eq!(position(&world, uncontrolled), pos!(9, 10));
     }
 }
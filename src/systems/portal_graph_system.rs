@@ -0,0 +1,61 @@
+use hashbrown::HashSet;
+use specs::shrev::ReaderId;
+use specs::{Read, System, World, WorldExt, Write};
+
+use crate::world::level::{ChunkEvent, TileChanged};
+use crate::world::portal_graph::PortalGraph;
+use crate::{Level, Position, TilePosition};
+
+/// Keeps the `PortalGraph` in sync with the loaded chunks and their tiles
+#[derive(Default)]
+pub struct PortalGraphSystem {
+    tile_changes: Option<ReaderId<TileChanged>>,
+    chunk_changes: Option<ReaderId<ChunkEvent>>,
+}
+
+impl<'a> System<'a> for PortalGraphSystem {
+    type SystemData = (Read<'a, Level>, Write<'a, PortalGraph>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (level, mut graph) = data;
+
+        // Entrances are shared with neighbours, so their entrances change along with the chunk
+        let mut stale = HashSet::<Position>::new();
+        if let Some(reader) = &mut self.chunk_changes {
+            for event in level.chunk_events.read(reader) {
+                let chunk_pos = match event {
+                    ChunkEvent::Loaded(chunk_pos) | ChunkEvent::Unloaded(chunk_pos) => *chunk_pos,
+                };
+                stale.insert(chunk_pos);
+                for side in PortalGraph::SIDES {
+                    stale.insert(chunk_pos + side);
+                }
+            }
+        }
+        if let Some(reader) = &mut self.tile_changes {
+            for change in level.tile_events.read(reader) {
+                stale.insert(TilePosition::to_chunk(change.tile_pos));
+                for side in PortalGraph::SIDES {
+                    stale.insert(TilePosition::to_chunk(change.tile_pos + side));
+                }
+            }
+        }
+
+        graph.retain_loaded(&level);
+        for chunk_pos in level.loaded_chunks.keys() {
+            if !graph.contains(*chunk_pos) {
+                stale.insert(*chunk_pos);
+            }
+        }
+        for chunk_pos in stale {
+            graph.build(chunk_pos, &level);
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.insert(PortalGraph::default());
+        let mut level = world.write_resource::<Level>();
+        self.tile_changes = Some(level.tile_events.register_reader());
+        self.chunk_changes = Some(level.chunk_events.register_reader());
+    }
+}
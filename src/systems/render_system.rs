@@ -1,7 +1,10 @@
-use ggez::graphics::MeshBuilder;
+use ggez::graphics::{Color, MeshBuilder};
 use specs::{Component, Join, Read, ReadStorage, RunningTime, System, WorldExt, WriteExpect};
 
 use crate::objects::sprite_atlas::SpriteId;
+use crate::systems::fov_system::Viewshed;
+use crate::world::light::LightMap;
+use crate::world::spatial_index::SpatialIndex;
 use crate::{pos, Camera, Control, Level, Position, SpriteAtlas, Tile, TilePosition, World};
 
 impl Component for SpriteId {
@@ -18,10 +21,23 @@ impl<'a> System<'a> for RenderSystem {
         ReadStorage<'a, SpriteId>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, Control>,
+        ReadStorage<'a, Viewshed>,
+        Read<'a, LightMap>,
+        Read<'a, SpatialIndex>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (level, mut atlas, mut camera, sprite_id, position, control) = data;
+        let (
+            level,
+            mut atlas,
+            mut camera,
+            sprite_id,
+            position,
+            control,
+            viewshed,
+            light_map,
+            index,
+        ) = data;
 
         atlas.clear();
 
@@ -34,17 +50,36 @@ impl<'a> System<'a> for RenderSystem {
                 );
         }
 
+        // Only what the player sees is shown, everything is shown if they have no viewshed
+        let visible = (&viewshed, &control)
+            .join()
+            .next()
+            .map(|(view, _)| &view.visible);
+
         // Render level
-        level.render(&mut *atlas, &*camera);
+        level.render(&mut *atlas, &*camera, visible, &light_map);
 
-        // Render entities
-        for (sprite_id, pos) in (&sprite_id, &position).join() {
+        // Render entities in view, the index is from before chunks were unloaded so entities are looked up again
+        let (min, max) = camera.tile_bounds();
+        for (entity, _) in index.in_rect(min, max) {
+            let (sprite_id, pos) = match (sprite_id.get(entity), position.get(entity)) {
+                (Some(sprite_id), Some(pos)) => (sprite_id, pos),
+                _ => continue,
+            };
+            if visible.is_some_and(|visible| !visible.contains(pos)) {
+                continue;
+            }
+            let color = match level.get_loaded_tile(*pos) {
+                Some(tile) => light_map.color_at(*pos, tile),
+                None => Color::WHITE,
+            };
             let screen_pos = TilePosition::to_screen(*pos, &*camera);
-            atlas.add(
+            atlas.add_tinted(
                 sprite_id,
                 screen_pos.x as f32,
                 screen_pos.y as f32,
                 camera.zoom,
+                color,
             );
         }
     }
@@ -0,0 +1,28 @@
+use specs::{Entities, Join, ReadStorage, System, World, WorldExt, Write};
+
+use crate::world::spatial_index::SpatialIndex;
+use crate::Position;
+
+/// Rebuilds the `SpatialIndex` from every positioned entity, runs once movement is done
+pub struct SpatialIndexSystem;
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, position, mut index) = data;
+
+        index.clear();
+        for (entity, pos) in (&entities, &position).join() {
+            index.insert(entity, *pos);
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<Position>();
+        world.insert(SpatialIndex::default());
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use specs::{
+    Component, Entities, Join, ReadStorage, System, VecStorage, World, WorldExt, Write,
+    WriteStorage,
+};
+
+use crate::{Control, Movement};
+
+/// How the world advances, every tick in real time or only once the player acts when turn based
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnMode {
+    RealTime,
+    TurnBased,
+}
+
+/// Whose turn it is when turn based
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnPhase {
+    /// The controlled entity is ready, the world waits for it to act
+    Player,
+    /// Every other ready entity acts
+    World,
+}
+
+/// Actions entities spend energy on when turn based
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Move,
+    Wait,
+    Attack,
+}
+
+impl Action {
+    pub fn cost(self) -> i32 {
+        match self {
+            Action::Move => Energy::ACTION,
+            Action::Wait => Energy::ACTION,
+            Action::Attack => Energy::ACTION,
+        }
+    }
+}
+
+/// Energy gained by `speed` every game turn, entities act once it reaches `Energy::ACTION`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Energy {
+    pub speed: i32,
+    pub energy: i32,
+}
+
+impl Component for Energy {
+    type Storage = VecStorage<Energy>;
+}
+
+impl Default for Energy {
+    fn default() -> Self {
+        Energy::new(Energy::NORMAL_SPEED)
+    }
+}
+
+impl Energy {
+    /// Energy needed to act
+    pub const ACTION: i32 = 100;
+    pub const NORMAL_SPEED: i32 = 10;
+
+    pub fn new(speed: i32) -> Self {
+        Energy { speed, energy: 0 }
+    }
+
+    #[inline(always)]
+    pub fn is_ready(&self) -> bool {
+        self.energy >= Energy::ACTION
+    }
+
+    pub fn spend(&mut self, action: Action) {
+        self.energy -= action.cost();
+    }
+
+    /// Game turns until ready, `None` if the entity never gains energy
+    pub fn turns_until_ready(&self) -> Option<i32> {
+        if self.is_ready() {
+            Some(0)
+        } else if self.speed <= 0 {
+            None
+        } else {
+            Some((Energy::ACTION - self.energy + self.speed - 1) / self.speed)
+        }
+    }
+}
+
+pub struct Turn {
+    pub mode: TurnMode,
+    pub phase: TurnPhase,
+    /// Game turns passed while turn based
+    pub count: u64,
+}
+
+impl Default for Turn {
+    fn default() -> Self {
+        Turn {
+            mode: TurnMode::RealTime,
+            phase: TurnPhase::Player,
+            count: 0,
+        }
+    }
+}
+
+impl Turn {
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            TurnMode::RealTime => TurnMode::TurnBased,
+            TurnMode::TurnBased => TurnMode::RealTime,
+        };
+    }
+
+    #[inline(always)]
+    pub fn is_turn_based(&self) -> bool {
+        self.mode == TurnMode::TurnBased
+    }
+
+    /// Whether an entity gets to act this tick, everyone acts every tick in real time
+    pub fn can_act(&self, energy: Option<&Energy>, controlled: bool) -> bool {
+        match self.mode {
+            TurnMode::RealTime => true,
+            TurnMode::TurnBased => {
+                energy.is_some_and(|energy| energy.is_ready())
+                    && controlled == (self.phase == TurnPhase::Player)
+            }
+        }
+    }
+}
+
+/// Energy scheduler of the turn based mode, runs before anything acts.
+///
+/// Ready entities other than the controlled one act first, then the world waits for the controlled
+/// entity to act. Once nobody is ready, game turns pass until someone is, so the world only advances
+/// after the player acted.
+pub struct TurnSystem;
+
+impl TurnSystem {
+    fn world_ready(energy: &WriteStorage<Energy>, control: &ReadStorage<Control>) -> bool {
+        (energy, !control)
+            .join()
+            .any(|(energy, _)| energy.is_ready())
+    }
+}
+
+impl<'a> System<'a> for TurnSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, Turn>,
+        WriteStorage<'a, Energy>,
+        ReadStorage<'a, Movement>,
+        ReadStorage<'a, Control>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut turn, mut energy, movement, control) = data;
+
+        // Entities spawned or saved without energy move at normal speed
+        let missing = (&entities, &movement, !&energy)
+            .join()
+            .map(|(entity, _, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in missing {
+            energy.insert(entity, Energy::default()).unwrap();
+        }
+
+        if !turn.is_turn_based() {
+            return;
+        }
+
+        if TurnSystem::world_ready(&energy, &control) {
+            turn.phase = TurnPhase::World;
+            return;
+        }
+        turn.phase = TurnPhase::Player;
+        if (&energy, &control)
+            .join()
+            .any(|(energy, _)| energy.is_ready())
+        {
+            return;
+        }
+
+        let turns = match (&energy)
+            .join()
+            .filter_map(|energy| energy.turns_until_ready())
+            .min()
+        {
+            Some(turns) => turns,
+            None => return,
+        };
+        for energy in (&mut energy).join() {
+            energy.energy += energy.speed.max(0) * turns;
+        }
+        turn.count += turns as u64;
+
+        if TurnSystem::world_ready(&energy, &control) {
+            turn.phase = TurnPhase::World;
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<Energy>();
+        world.register::<Movement>();
+        world.register::<Control>();
+        world.insert(Turn::default());
+    }
+}
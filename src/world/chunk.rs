@@ -1,12 +1,18 @@
+use ggez::graphics::Color;
 use ggez::GameResult;
+use hashbrown::HashSet;
 use std::sync::Arc;
 
-use noise::{NoiseFn, Perlin, Seedable};
-use specs::world::EntitiesRes;
-use specs::Entity;
+use serde::{Deserialize, Serialize};
 
+use crate::world::compressed_tiles::CompressedTiles;
 use crate::world::direction::{DirectionalMap, DIRECTIONS};
+use crate::world::fog::Fog;
+use crate::world::generation::WorldGenerator;
+use crate::world::light::LightMap;
 use crate::world::position::TilePosition;
+use crate::world::tile::TileId;
+use crate::world::tile_registry::TileRegistry;
 use crate::{pos, Camera, ChunkPosition, Level, SpriteAtlas, Tile, WorldPosition};
 
 use super::position::Position;
@@ -19,56 +25,51 @@ impl Chunk {
     pub const SIZE: i32 = 32;
 }
 
-type Tiles = [[Tile; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
+pub type Tiles = [[Tile; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct UnloadedChunk {
-    pub tiles: Tiles,
+    pub tiles: CompressedTiles,
     pub pos: Position,
-    pub entities: Vec<Entity>,
+    /// Entities that were in the chunk when it was unloaded, see `ChunkEntities`
+    pub entities: Vec<u8>,
+    /// What the player explored of the chunk
+    pub fog: Fog,
+    /// Whether the chunk differs from its stored copy
+    #[serde(skip)]
+    pub dirty: bool,
 }
 
 impl UnloadedChunk {
-    pub fn generate(chunk_pos: Position, seed: i32) -> Self {
+    pub fn generate(chunk_pos: Position, generator: &dyn WorldGenerator) -> Self {
         let mut tiles = [[Tile::empty(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
-
-        let mut perlin = Perlin::new();
-        perlin = Seedable::set_seed(perlin, seed as u32);
-
-        for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let val = perlin.get([
-                    (x + chunk_pos.x * CHUNK_SIZE) as f64 / 15.0,
-                    (y + chunk_pos.y * CHUNK_SIZE) as f64 / 15.0,
-                ]);
-
-                if val > 0.5 {
-                    tiles[x as usize][y as usize] = Tile::rock();
-                }
-            }
-        }
+        generator.generate(chunk_pos, &mut tiles);
 
         UnloadedChunk {
-            tiles,
+            tiles: CompressedTiles::compress(&tiles),
             pos: chunk_pos,
             entities: Vec::with_capacity(0),
+            fog: Fog::default(),
+            dirty: false,
         }
     }
 
-    pub fn load(self /*, entities_res: EntitiesRes*/) -> LoadedChunk {
-        /*
-        for entity in self.entities {
-            entities_res
-                .build_entity()
-                //.with()
-                .build();
-        }
-        */
+    /// Approximate memory used by the chunk in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        std::mem::size_of::<UnloadedChunk>()
+            + self.tiles.size_in_bytes()
+            + self.entities.len()
+            + self.fog.size_in_bytes()
+    }
 
+    /// Entities have to be taken out beforehand, they are dropped with the unloaded chunk
+    pub fn load(self) -> LoadedChunk {
         LoadedChunk {
-            tiles: self.tiles,
+            tiles: self.tiles.decompress(),
             neighbours: Default::default(),
             pos: self.pos,
+            fog: self.fog,
+            dirty: self.dirty,
         }
     }
 }
@@ -78,14 +79,22 @@ pub struct LoadedChunk {
     pub tiles: [[Tile; Chunk::SIZE as usize]; Chunk::SIZE as usize],
     pub neighbours: DirectionalMap<Option<Arc<LoadedChunk>>>,
     pub pos: Position,
+    pub fog: Fog,
+    /// Whether the chunk differs from its stored copy, clean chunks are not written when unloaded
+    pub dirty: bool,
 }
 
 impl LoadedChunk {
+    /// Tint of explored tiles that are out of sight
+    pub const REMEMBERED_COLOR: Color = Color::new(0.4, 0.4, 0.5, 1.0);
+
     pub fn void() -> Self {
         LoadedChunk {
             tiles: [[Tile::empty(); Chunk::SIZE as usize]; Chunk::SIZE as usize],
             neighbours: Default::default(),
             pos: pos!(0, 0),
+            fog: Fog::default(),
+            dirty: false,
         }
     }
 
@@ -105,23 +114,69 @@ impl LoadedChunk {
         return self.get_tile(tile_pos.x, tile_pos.y);
     }
 
-    pub fn render(&self, atlas: &mut SpriteAtlas, camera: &Camera) -> GameResult {
-        for x in 0..CHUNK_SIZE {
-            for y in 0..CHUNK_SIZE {
-                let tile_pos = pos!(x, y) + ChunkPosition::to_tile(self.pos);
-                if !camera.tile_in_view(tile_pos) {
-                    continue;
+    /// Replaces the tile at the position within the chunk, returning the previous tile
+    pub fn set_tile_at(&mut self, tile_pos: Position, tile: Tile) -> Tile {
+        self.dirty = true;
+        std::mem::replace(
+            &mut self.tiles[tile_pos.x as usize][tile_pos.y as usize],
+            tile,
+        )
+    }
+
+    /// Draws the floors of all tiles first, then their objects and then their overlays.
+    ///
+    /// With a set of visible tiles, unexplored tiles are skipped and explored tiles out of sight
+    /// are drawn dimmed as they were last seen. Tiles in sight are tinted by the light on them.
+    pub fn render(
+        &self,
+        atlas: &mut SpriteAtlas,
+        camera: &Camera,
+        visible: Option<&HashSet<Position>>,
+        light_map: &LightMap,
+    ) -> GameResult {
+        let passes: [fn(&Tile) -> Option<TileId>; 3] = [
+            |tile| Some(tile.floor),
+            |tile| tile.object,
+            |tile| tile.overlay,
+        ];
+        let registry = TileRegistry::global();
+
+        for layer_of in passes {
+            for x in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let tile_pos = pos!(x, y) + ChunkPosition::to_tile(self.pos);
+                    if !camera.tile_in_view(tile_pos) {
+                        continue;
+                    }
+                    let (tile, color) = match visible {
+                        Some(visible) if !visible.contains(&tile_pos) => {
+                            if !self.fog.is_explored(pos!(x, y)) {
+                                continue;
+                            }
+                            let tile = self
+                                .fog
+                                .remembered_at(pos!(x, y))
+                                .unwrap_or(&self.tiles[x as usize][y as usize]);
+                            (tile, LoadedChunk::REMEMBERED_COLOR)
+                        }
+                        _ => {
+                            let tile = &self.tiles[x as usize][y as usize];
+                            (tile, light_map.color_at(tile_pos, tile))
+                        }
+                    };
+                    let layer = match layer_of(tile) {
+                        Some(id) => registry.def(id),
+                        None => continue,
+                    };
+                    let tile_screen_pos = TilePosition::to_screen(tile_pos, camera);
+                    atlas.add_tinted(
+                        &tile.sprite_of(layer),
+                        tile_screen_pos.x as f32,
+                        tile_screen_pos.y as f32,
+                        camera.zoom,
+                        color,
+                    );
                 }
-                let x = x as usize;
-                let y = y as usize;
-                let tile = &self.tiles[x][y];
-                let tile_screen_pos = TilePosition::to_screen(tile_pos, camera);
-                atlas.add(
-                    &tile.sprite_id,
-                    tile_screen_pos.x as f32,
-                    tile_screen_pos.y as f32,
-                    camera.zoom,
-                );
             }
         }
         Ok(())
@@ -227,11 +282,24 @@ impl LoadedChunk {
             }
         }
     */
-    pub fn unload(self, entities: Vec<Entity>) -> UnloadedChunk {
+    /// Copies the chunk data without unloading it, used when saving chunks that stay loaded
+    pub fn snapshot(&self) -> UnloadedChunk {
+        UnloadedChunk {
+            tiles: CompressedTiles::compress(&self.tiles),
+            pos: self.pos,
+            entities: Vec::with_capacity(0),
+            fog: self.fog.clone(),
+            dirty: self.dirty,
+        }
+    }
+
+    pub fn unload(self, entities: Vec<u8>) -> UnloadedChunk {
         UnloadedChunk {
-            tiles: self.tiles,
+            tiles: CompressedTiles::compress(&self.tiles),
             pos: self.pos,
+            dirty: self.dirty || !entities.is_empty(),
             entities,
+            fog: self.fog,
         }
     }
 }
@@ -0,0 +1,87 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::world::chunk::UnloadedChunk;
+use crate::world::generation::WorldGenerator;
+use crate::Position;
+
+/// Worker threads generating chunks in the background.
+///
+/// Chunk positions are sent to the workers with `request`, generated chunks are collected with `finished`.
+/// Workers shut down when the pool is dropped.
+#[derive(Debug)]
+pub struct ChunkPool {
+    requests: Mutex<Sender<Position>>,
+    results: Mutex<Receiver<UnloadedChunk>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ChunkPool {
+    pub fn new(generator: Arc<dyn WorldGenerator>) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel::<Position>();
+        let (result_sender, result_receiver) = mpsc::channel::<UnloadedChunk>();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+
+        let workers = (0..ChunkPool::worker_count())
+            .map(|i| {
+                let requests = Arc::clone(&request_receiver);
+                let results = result_sender.clone();
+                let generator = Arc::clone(&generator);
+                thread::Builder::new()
+                    .name(format!("chunk-worker-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before generating so other workers can pick up requests
+                        let request = requests.lock().unwrap().recv();
+                        let chunk_pos = match request {
+                            Ok(chunk_pos) => chunk_pos,
+                            Err(_) => break,
+                        };
+
+                        let chunk = UnloadedChunk::generate(chunk_pos, &*generator);
+                        if results.send(chunk).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Failed to spawn chunk worker")
+            })
+            .collect();
+
+        ChunkPool {
+            requests: Mutex::new(request_sender),
+            results: Mutex::new(result_receiver),
+            workers,
+        }
+    }
+
+    /// Leaves one core for the main thread
+    fn worker_count() -> usize {
+        thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn request(&self, chunk_pos: Position) {
+        self.requests
+            .lock()
+            .unwrap()
+            .send(chunk_pos)
+            .expect("Chunk workers have shut down");
+    }
+
+    /// Returns all chunks generated since the last call, without blocking
+    pub fn finished(&self) -> Vec<UnloadedChunk> {
+        self.results.lock().unwrap().try_iter().collect()
+    }
+}
+
+impl Drop for ChunkPool {
+    fn drop(&mut self) {
+        // Replacing the sender closes the request channel, letting the workers finish their loop
+        *self.requests.lock().unwrap() = mpsc::channel().0;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
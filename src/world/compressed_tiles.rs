@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+use crate::world::chunk::{Chunk, Tiles};
+use crate::Tile;
+
+/// Tiles of a chunk stored as a palette of the distinct tiles and runs of palette indices.
+///
+/// Tiles are visited column by column, the same order as `Tiles` is indexed in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressedTiles {
+    pub palette: Vec<Tile>,
+    /// Run length and palette index pairs
+    pub runs: Vec<(u16, u16)>,
+}
+
+impl CompressedTiles {
+    const TILE_COUNT: usize = (Chunk::SIZE * Chunk::SIZE) as usize;
+
+    pub fn compress(tiles: &Tiles) -> Self {
+        let mut palette = Vec::<Tile>::new();
+        let mut runs = Vec::<(u16, u16)>::new();
+
+        for tile in tiles.iter().flatten() {
+            let index = match palette.iter().position(|entry| entry == tile) {
+                Some(index) => index,
+                None => {
+                    palette.push(*tile);
+                    palette.len() - 1
+                }
+            } as u16;
+
+            match runs.last_mut() {
+                Some((length, run_index)) if *run_index == index => *length += 1,
+                _ => runs.push((1, index)),
+            }
+        }
+
+        CompressedTiles { palette, runs }
+    }
+
+    /// Panics on invalid data, see `CompressedTiles::validate`
+    pub fn decompress(&self) -> Tiles {
+        let mut tiles = [[Tile::empty(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        let mut cells = tiles.iter_mut().flatten();
+        for (length, index) in &self.runs {
+            let tile = self.palette[*index as usize];
+            for cell in cells.by_ref().take(*length as usize) {
+                *cell = tile;
+            }
+        }
+        tiles
+    }
+
+    /// Checks that the runs cover the whole chunk and only refer to tiles in the palette
+    pub fn validate(&self) -> Result<(), String> {
+        let mut count = 0;
+        for (length, index) in &self.runs {
+            if *index as usize >= self.palette.len() {
+                return Err(format!(
+                    "Palette index {} out of range, the palette has {} tiles",
+                    index,
+                    self.palette.len()
+                ));
+            }
+            count += *length as usize;
+        }
+
+        if count != CompressedTiles::TILE_COUNT {
+            return Err(format!(
+                "Runs cover {} tiles instead of {}",
+                count,
+                CompressedTiles::TILE_COUNT
+            ));
+        }
+        Ok(())
+    }
+
+    /// Approximate heap memory used by the compressed tiles
+    pub fn size_in_bytes(&self) -> usize {
+        self.palette.len() * std::mem::size_of::<Tile>()
+            + self.runs.len() * std::mem::size_of::<(u16, u16)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::generation::GeneratorKind;
+    use crate::world::tile::TileId;
+    use crate::{pos, Position};
+
+    fn roundtrip(tiles: &Tiles) -> CompressedTiles {
+        let compressed = CompressedTiles::compress(tiles);
+        compressed.validate().unwrap();
+        assert_eq!(compressed.decompress(), *tiles);
+        compressed
+    }
+
+    #[test]
+    fn compress_roundtrips() {
+        let uniform = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        let compressed = roundtrip(&uniform);
+        assert_eq!(compressed.palette.len(), 1);
+        assert_eq!(
+            compressed.runs,
+            vec![(CompressedTiles::TILE_COUNT as u16, 0)]
+        );
+
+        // Alternating in visiting order, so every tile is a run of its own
+        let mut checkered = uniform;
+        for (i, tile) in checkered.iter_mut().flatten().enumerate() {
+            if i % 2 == 0 {
+                *tile = Tile::sand().with_object(TileId::rock());
+            }
+        }
+        let compressed = roundtrip(&checkered);
+        assert_eq!(compressed.palette.len(), 2);
+        assert_eq!(compressed.runs.len(), CompressedTiles::TILE_COUNT);
+
+        let generator = GeneratorKind::Layered(vec![
+            GeneratorKind::Biomes,
+            GeneratorKind::Dungeon { size: None },
+        ])
+        .build(11);
+        for chunk_pos in [pos!(0, 0), pos!(-3, 2)] {
+            let mut tiles = uniform;
+            generator.generate(chunk_pos, &mut tiles);
+            roundtrip(&tiles);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_palette_indices() {
+        let tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        let mut compressed = CompressedTiles::compress(&tiles);
+        compressed.runs = vec![(1000, 0), (24, 1)];
+        assert!(compressed.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_runs_not_covering_the_chunk() {
+        let tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        let mut compressed = CompressedTiles::compress(&tiles);
+        compressed.runs = vec![(1000, 0)];
+        assert!(compressed.validate().is_err());
+        compressed.runs = vec![(1000, 0), (25, 0)];
+        assert!(compressed.validate().is_err());
+        compressed.runs = Vec::new();
+        assert!(compressed.validate().is_err());
+
+        compressed.runs = vec![(1000, 0), (24, 0)];
+        compressed.validate().unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use hashbrown::HashMap;
+
+use crate::world::direction::DIRECTIONS;
+use crate::world::occupancy::Occupancy;
+use crate::{Level, Position};
+
+/// Cost of the cheapest path from every reachable tile around `center` to the nearest goal.
+///
+/// Moving to the cheapest neighbour leads to a goal from anywhere on the map, so any number of
+/// entities can share one map instead of searching a path each. Only passable tiles of loaded
+/// chunks within `range` tiles of `center` are on the map.
+#[derive(Debug, Clone)]
+pub struct DijkstraMap {
+    costs: HashMap<Position, i32>,
+    pub center: Position,
+    pub range: i32,
+}
+
+impl DijkstraMap {
+    /// Scales costs when deriving a flee map, beyond -1 fleeing entities prefer open areas over corners
+    pub const FLEE_COEFFICIENT: f32 = -1.2;
+
+    pub fn new(goals: &[Position], center: Position, range: i32, level: &Level) -> Self {
+        let mut map = DijkstraMap {
+            costs: HashMap::new(),
+            center,
+            range,
+        };
+        let seeds = goals
+            .iter()
+            .filter(|goal| map.in_range(**goal) && !level.is_blocked(**goal))
+            .map(|goal| (*goal, 0))
+            .collect();
+        map.relax(seeds, level);
+        map
+    }
+
+    /// Map leading away from the goals of this map, towards tiles far from them.
+    ///
+    /// Costs are scaled by `FLEE_COEFFICIENT` and relaxed again, so the safest tiles become the new goals
+    /// while paths past the goals stay more expensive than dead ends next to them.
+    pub fn flee(&self, level: &Level) -> DijkstraMap {
+        let mut map = DijkstraMap {
+            costs: HashMap::new(),
+            center: self.center,
+            range: self.range,
+        };
+        let seeds = self
+            .costs
+            .iter()
+            .map(|(pos, cost)| (*pos, (*cost as f32 * DijkstraMap::FLEE_COEFFICIENT) as i32))
+            .collect();
+        map.relax(seeds, level);
+        map
+    }
+
+    /// Dijkstra from every seed at once, seeds start at their own cost instead of 0
+    fn relax(&mut self, seeds: Vec<(Position, i32)>, level: &Level) {
+        let mut open = BinaryHeap::new();
+        for (pos, cost) in seeds {
+            self.costs.insert(pos, cost);
+            open.push(Reverse((cost, pos.x, pos.y)));
+        }
+
+        while let Some(Reverse((cost, x, y))) = open.pop() {
+            let pos = Position { x, y };
+            if self.costs.get(&pos).is_some_and(|best| *best < cost) {
+                continue;
+            }
+            for dir in DIRECTIONS {
+                let n_pos = pos + dir;
+                if !self.in_range(n_pos) {
+                    continue;
+                }
+                let tile = match level.get_loaded_tile(n_pos) {
+                    Some(tile) if !tile.blocked() => tile,
+                    _ => continue,
+                };
+                let n_cost = cost.saturating_add(tile.traversal_cost());
+                if self.costs.get(&n_pos).is_none_or(|best| n_cost < *best) {
+                    self.costs.insert(n_pos, n_cost);
+                    open.push(Reverse((n_cost, n_pos.x, n_pos.y)));
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn in_range(&self, tile_pos: Position) -> bool {
+        tile_pos.dist(self.center) <= self.range
+    }
+
+    pub fn contains(&self, tile_pos: Position) -> bool {
+        self.costs.contains_key(&tile_pos)
+    }
+
+    pub fn cost_at(&self, tile_pos: Position) -> Option<i32> {
+        self.costs.get(&tile_pos).copied()
+    }
+
+    /// The cheapest neighbour of a tile if it is cheaper than the tile itself, ties go to the first in
+    /// `DIRECTIONS` order. Occupied neighbours cost extra, so entities queue up or go around each other.
+    pub fn descend(&self, tile_pos: Position, occupancy: &Occupancy) -> Option<Position> {
+        let mut best = (tile_pos, self.cost_at(tile_pos)?);
+        for dir in DIRECTIONS {
+            let n_pos = tile_pos + dir;
+            if let Some(cost) = self.cost_at(n_pos) {
+                let cost = cost + occupancy.cost_at(n_pos);
+                if cost < best.1 {
+                    best = (n_pos, cost);
+                }
+            }
+        }
+        if best.0 == tile_pos {
+            None
+        } else {
+            Some(best.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pos;
+    use crate::world::level::tests::hand_built_level;
+
+    /// Grass chunk split by a wall at x = 10 with a gap at the bottom
+    fn walled_level() -> Level {
+        let walls = (0..25).map(|y| pos!(10, y)).collect::<Vec<_>>();
+        hand_built_level(&[pos!(0, 0)], &walls)
+    }
+
+    /// Tiles visited when always stepping to the cheapest neighbour
+    fn follow(map: &DijkstraMap, from: Position) -> Vec<Position> {
+        let occupancy = Occupancy::default();
+        let mut path = vec![from];
+        while let Some(next) = map.descend(*path.last().unwrap(), &occupancy) {
+            path.push(next);
+            assert!(path.len() < 200, "descending does not end");
+        }
+        path
+    }
+
+    #[test]
+    fn descending_reaches_the_goal() {
+        let level = walled_level();
+        let goal = pos!(15, 5);
+        let map = DijkstraMap::new(&[goal], goal, 30, &level);
+
+        assert_eq!(map.cost_at(goal), Some(0));
+        assert!(!map.contains(pos!(10, 5)));
+        let path = follow(&map, pos!(5, 5));
+        assert_eq!(path.last(), Some(&goal));
+        // Around the wall through the gap
+        assert!(path.iter().any(|tile_pos| tile_pos.y >= 25));
+        for pair in path.windows(2) {
+            assert!(map.cost_at(pair[1]) < map.cost_at(pair[0]));
+        }
+    }
+
+    #[test]
+    fn fleeing_moves_away_from_the_goal() {
+        let level = walled_level();
+        let goal = pos!(15, 5);
+        let map = DijkstraMap::new(&[goal], goal, 30, &level);
+        let flee = map.flee(&level);
+
+        let start = pos!(17, 6);
+        let path = follow(&flee, start);
+        assert!(path.len() > 1);
+        let start_cost = map.cost_at(start).unwrap();
+        assert!(map.cost_at(path[1]).unwrap() > start_cost);
+        assert!(map.cost_at(*path.last().unwrap()).unwrap() > start_cost + 5);
+        for pair in path.windows(2) {
+            assert!(flee.cost_at(pair[1]) < flee.cost_at(pair[0]));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::chunk::Chunk;
+use crate::{Position, Tile};
+
+/// What the player knows about the tiles of a chunk, stored and persisted with the chunk.
+///
+/// Explored tiles are drawn even when out of sight, using their remembered appearance if they
+/// changed since they were last seen.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fog {
+    /// One bit per tile, bit `y` of column `x`
+    explored: [u32; Chunk::SIZE as usize],
+    /// Last seen tiles of explored tiles that changed out of sight, keyed by `x * SIZE + y`
+    remembered: BTreeMap<u16, Tile>,
+}
+
+impl Fog {
+    #[inline(always)]
+    fn key(tile_chunk_pos: Position) -> u16 {
+        (tile_chunk_pos.x * Chunk::SIZE + tile_chunk_pos.y) as u16
+    }
+
+    pub fn is_explored(&self, tile_chunk_pos: Position) -> bool {
+        self.explored[tile_chunk_pos.x as usize] & (1 << tile_chunk_pos.y) != 0
+    }
+
+    /// Marks a tile as explored and forgets its remembered appearance, returns whether anything changed
+    pub fn explore(&mut self, tile_chunk_pos: Position) -> bool {
+        let column = &mut self.explored[tile_chunk_pos.x as usize];
+        let unexplored = *column & (1 << tile_chunk_pos.y) == 0;
+        *column |= 1 << tile_chunk_pos.y;
+        let forgot = self.remembered.remove(&Fog::key(tile_chunk_pos)).is_some();
+        unexplored || forgot
+    }
+
+    /// Keeps the appearance of an explored tile that changed out of sight, only the first change is kept
+    pub fn remember(&mut self, tile_chunk_pos: Position, tile: Tile) -> bool {
+        if !self.is_explored(tile_chunk_pos) {
+            return false;
+        }
+        match self.remembered.entry(Fog::key(tile_chunk_pos)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(tile);
+                true
+            }
+        }
+    }
+
+    pub fn remembered_at(&self, tile_chunk_pos: Position) -> Option<&Tile> {
+        self.remembered.get(&Fog::key(tile_chunk_pos))
+    }
+
+    pub fn remembered(&self) -> impl Iterator<Item = &Tile> {
+        self.remembered.values()
+    }
+
+    /// Approximate memory used by the fog in bytes, on top of its own size
+    pub fn size_in_bytes(&self) -> usize {
+        self.remembered.len() * (std::mem::size_of::<u16>() + std::mem::size_of::<Tile>())
+    }
+}
@@ -0,0 +1,201 @@
+use hashbrown::HashSet;
+
+use crate::{pos, Position};
+
+/// Field of view using symmetric shadowcasting.
+///
+/// Each quadrant around the origin is scanned row by row, rows are narrowed by the slopes of the
+/// opaque tiles in previous rows. A floor tile is only visible when its center is within the scanned
+/// slopes, which makes the result symmetric: if A can see B then B can see A.
+pub struct Fov;
+
+/// Slope as a fraction, `den` is always positive
+#[derive(Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    fn min_col(&self) -> i32 {
+        // Rounds depth * start with ties going up
+        (2 * self.depth * self.start.num + self.start.den).div_euclid(2 * self.start.den)
+    }
+
+    fn max_col(&self) -> i32 {
+        // Rounds depth * end with ties going down
+        -(-(2 * self.depth * self.end.num - self.end.den)).div_euclid(2 * self.end.den)
+    }
+
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num
+            && col * self.end.den <= self.depth * self.end.num
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl Fov {
+    /// Returns every tile visible from `origin` within `range` tiles, `is_opaque` tells whether
+    /// a tile blocks sight. Opaque tiles are visible themselves.
+    pub fn compute<F: Fn(Position) -> bool>(
+        origin: Position,
+        range: i32,
+        is_opaque: F,
+    ) -> HashSet<Position> {
+        let mut visible = HashSet::new();
+        visible.insert(origin);
+
+        // North, east, south and west, each as a transform from (depth, col) to a tile position
+        let quadrants: [fn(Position, i32, i32) -> Position; 4] = [
+            |origin, depth, col| origin + pos!(col, -depth),
+            |origin, depth, col| origin + pos!(depth, col),
+            |origin, depth, col| origin + pos!(col, depth),
+            |origin, depth, col| origin + pos!(-depth, col),
+        ];
+
+        for transform in quadrants {
+            let first_row = Row {
+                depth: 1,
+                start: Slope { num: -1, den: 1 },
+                end: Slope { num: 1, den: 1 },
+            };
+            Fov::scan(
+                first_row,
+                &|depth, col| transform(origin, depth, col),
+                origin,
+                range,
+                &is_opaque,
+                &mut visible,
+            );
+        }
+
+        visible
+    }
+
+    fn scan<T: Fn(i32, i32) -> Position, F: Fn(Position) -> bool>(
+        mut row: Row,
+        transform: &T,
+        origin: Position,
+        range: i32,
+        is_opaque: &F,
+        visible: &mut HashSet<Position>,
+    ) {
+        if row.depth > range {
+            return;
+        }
+
+        // Opaqueness of the previous tile in the row, `None` before the first tile
+        let mut prev_opaque = None;
+        for col in row.min_col()..=row.max_col() {
+            let tile_pos = transform(row.depth, col);
+            let opaque = is_opaque(tile_pos);
+
+            if (opaque || row.is_symmetric(col)) && Fov::in_range(origin, tile_pos, range) {
+                visible.insert(tile_pos);
+            }
+            if prev_opaque == Some(true) && !opaque {
+                row.start = Fov::slope(row.depth, col);
+            }
+            if prev_opaque == Some(false) && opaque {
+                let mut next_row = row.next();
+                next_row.end = Fov::slope(row.depth, col);
+                Fov::scan(next_row, transform, origin, range, is_opaque, visible);
+            }
+            prev_opaque = Some(opaque);
+        }
+
+        if prev_opaque == Some(false) {
+            Fov::scan(row.next(), transform, origin, range, is_opaque, visible);
+        }
+    }
+
+    /// Slope of the left edge of a tile
+    fn slope(depth: i32, col: i32) -> Slope {
+        Slope {
+            num: 2 * col - 1,
+            den: 2 * depth,
+        }
+    }
+
+    /// Keeps the field of view circular instead of square
+    fn in_range(origin: Position, tile_pos: Position, range: i32) -> bool {
+        let delta = tile_pos - origin;
+        delta.x * delta.x + delta.y * delta.y <= range * range + range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashSet;
+
+    use super::Fov;
+    use crate::{pos, Position};
+
+    /// Scattered pillars around the origin
+    fn pillars() -> HashSet<Position> {
+        (-12..=12)
+            .flat_map(|x| (-12..=12).map(move |y| pos!(x, y)))
+            .filter(|tile_pos| (tile_pos.x * 5 + tile_pos.y * 3).rem_euclid(7) == 0)
+            .filter(|tile_pos| *tile_pos != pos!(0, 0))
+            .collect()
+    }
+
+    #[test]
+    fn fov_is_symmetric() {
+        let walls = pillars();
+        let range = 8;
+        let origin = pos!(0, 0);
+        let visible = Fov::compute(origin, range, |tile_pos| walls.contains(&tile_pos));
+
+        let mut seen = 0;
+        for x in -range..=range {
+            for y in -range..=range {
+                let other = pos!(x, y);
+                if walls.contains(&other) || !Fov::in_range(origin, other, range) {
+                    continue;
+                }
+                let back = Fov::compute(other, range, |tile_pos| walls.contains(&tile_pos));
+                assert_eq!(
+                    visible.contains(&other),
+                    back.contains(&origin),
+                    "{:?}",
+                    other
+                );
+                seen += visible.contains(&other) as i32;
+            }
+        }
+        assert!(seen > 0);
+    }
+
+    #[test]
+    fn walls_occlude_tiles_behind_them() {
+        // Wall from (3, -2) to (3, 2) east of the origin
+        let walls = (-2..=2).map(|y| pos!(3, y)).collect::<HashSet<_>>();
+        let visible = Fov::compute(pos!(0, 0), 10, |tile_pos| walls.contains(&tile_pos));
+
+        for wall in &walls {
+            assert!(visible.contains(wall), "{:?}", wall);
+        }
+        for x in 4..=10 {
+            assert!(!visible.contains(&pos!(x, 0)), "{:?}", x);
+        }
+        assert!(!visible.contains(&pos!(6, 1)));
+        assert!(visible.contains(&pos!(2, 0)));
+        assert!(visible.contains(&pos!(-10, 0)));
+        assert!(visible.contains(&pos!(0, 10)));
+        assert!(!visible.contains(&pos!(0, 11)));
+    }
+}
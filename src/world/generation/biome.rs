@@ -0,0 +1,132 @@
+use noise::{NoiseFn, Perlin, Seedable};
+
+use crate::world::chunk::{Chunk, Tiles};
+use crate::world::generation::{tile_random, WorldGenerator};
+use crate::world::position::ChunkPosition;
+use crate::world::tile::TileId;
+use crate::{pos, Position, Tile};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Biome {
+    Forest,
+    Desert,
+    Tundra,
+    Swamp,
+    Mountains,
+}
+
+/// Tiles a biome is built from, the floor tile decides the traversal cost of the biome
+pub struct BiomePalette {
+    pub floor: fn() -> Tile,
+    pub obstacles: &'static [fn() -> TileId],
+    /// Chance of a tile being an obstacle instead of floor
+    pub obstacle_density: f64,
+}
+
+impl Biome {
+    /// Classifies a tile from its noise values, all roughly in `[-1, 1]`
+    pub fn classify(temperature: f64, moisture: f64, elevation: f64) -> Biome {
+        if elevation > 0.4 {
+            Biome::Mountains
+        } else if temperature < -0.25 {
+            Biome::Tundra
+        } else if moisture > 0.3 {
+            Biome::Swamp
+        } else if temperature > 0.2 && moisture < 0.0 {
+            Biome::Desert
+        } else {
+            Biome::Forest
+        }
+    }
+
+    pub fn palette(self) -> BiomePalette {
+        match self {
+            Biome::Forest => BiomePalette {
+                floor: Tile::grass,
+                obstacles: &[TileId::tree, TileId::pine],
+                obstacle_density: 0.2,
+            },
+            Biome::Desert => BiomePalette {
+                floor: Tile::sand,
+                obstacles: &[TileId::rock],
+                obstacle_density: 0.02,
+            },
+            Biome::Tundra => BiomePalette {
+                floor: Tile::snow,
+                obstacles: &[TileId::pine],
+                obstacle_density: 0.05,
+            },
+            Biome::Swamp => BiomePalette {
+                floor: Tile::marsh,
+                obstacles: &[TileId::tree],
+                obstacle_density: 0.1,
+            },
+            Biome::Mountains => BiomePalette {
+                floor: Tile::gravel,
+                obstacles: &[TileId::rock],
+                obstacle_density: 0.5,
+            },
+        }
+    }
+}
+
+/// Classifies every tile into a biome from temperature, moisture and elevation noise layers,
+/// then fills it from that biome's palette
+#[derive(Debug, Clone)]
+pub struct BiomeGenerator {
+    seed: i32,
+    temperature: Perlin,
+    moisture: Perlin,
+    elevation: Perlin,
+}
+
+impl BiomeGenerator {
+    pub const TEMPERATURE_SCALE: f64 = 200.0;
+    pub const MOISTURE_SCALE: f64 = 150.0;
+    pub const ELEVATION_SCALE: f64 = 100.0;
+
+    pub fn new(seed: i32) -> Self {
+        BiomeGenerator {
+            seed,
+            temperature: Perlin::default().set_seed(seed.wrapping_add(1) as u32),
+            moisture: Perlin::default().set_seed(seed.wrapping_add(2) as u32),
+            elevation: Perlin::default().set_seed(seed.wrapping_add(3) as u32),
+        }
+    }
+
+    pub fn biome_at(&self, tile_pos: Position) -> Biome {
+        let sample = |perlin: &Perlin, scale: f64| {
+            perlin.get([tile_pos.x as f64 / scale, tile_pos.y as f64 / scale])
+        };
+
+        Biome::classify(
+            sample(&self.temperature, BiomeGenerator::TEMPERATURE_SCALE),
+            sample(&self.moisture, BiomeGenerator::MOISTURE_SCALE),
+            sample(&self.elevation, BiomeGenerator::ELEVATION_SCALE),
+        )
+    }
+}
+
+impl WorldGenerator for BiomeGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        let chunk_tile_pos = ChunkPosition::to_tile(chunk_pos);
+
+        for y in 0..Chunk::SIZE {
+            for x in 0..Chunk::SIZE {
+                let tile_pos = chunk_tile_pos + pos!(x, y);
+                let palette = self.biome_at(tile_pos).palette();
+
+                let roll = tile_random(self.seed, tile_pos);
+                let mut tile = (palette.floor)();
+                if roll < palette.obstacle_density {
+                    // Reuse the roll to pick the obstacle, it is uniform within the density range
+                    let index =
+                        (roll / palette.obstacle_density * palette.obstacles.len() as f64) as usize;
+                    tile = tile
+                        .with_object((palette.obstacles[index.min(palette.obstacles.len() - 1)])());
+                }
+                tiles[x as usize][y as usize] = tile;
+            }
+        }
+    }
+}
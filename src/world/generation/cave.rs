@@ -0,0 +1,118 @@
+use crate::world::chunk::{Chunk, Tiles};
+use crate::world::direction::DIRECTIONS;
+use crate::world::generation::{tile_random, WorldGenerator};
+use crate::world::position::ChunkPosition;
+use crate::{pos, Position, Tile};
+
+/// Seeds walls from noise and smooths them into caves with cellular automata.
+///
+/// Every chunk simulates its own area plus a margin of `passes + 1` tiles. Each pass can only
+/// compute cells whose neighbours were valid in the previous pass, so the valid area shrinks by one
+/// tile per pass. With that margin the chunk and a one tile ring around it end up exactly as they
+/// would on an infinite grid, so neighbouring chunks always agree on their boundary tiles.
+#[derive(Debug, Clone)]
+pub struct CaveGenerator {
+    seed: i32,
+    /// Chance of a tile starting out as wall
+    pub fill_chance: f64,
+    pub passes: usize,
+}
+
+impl CaveGenerator {
+    pub const FILL_CHANCE: f64 = 0.5;
+    pub const PASSES: usize = 4;
+
+    pub fn new(seed: i32) -> Self {
+        CaveGenerator {
+            seed,
+            fill_chance: CaveGenerator::FILL_CHANCE,
+            passes: CaveGenerator::PASSES,
+        }
+    }
+
+    fn wall_neighbours(walls: &[Vec<bool>], x: usize, y: usize) -> [bool; 8] {
+        DIRECTIONS.map(|dir| walls[(x as i32 + dir.x) as usize][(y as i32 + dir.y) as usize])
+    }
+}
+
+impl WorldGenerator for CaveGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        let margin = self.passes + 1;
+        let size = Chunk::SIZE as usize + margin * 2;
+        let origin = ChunkPosition::to_tile(chunk_pos) - pos!(margin as i32, margin as i32);
+
+        let mut walls = vec![vec![false; size]; size];
+        for (x, column) in walls.iter_mut().enumerate() {
+            for (y, wall) in column.iter_mut().enumerate() {
+                *wall =
+                    tile_random(self.seed, origin + pos!(x as i32, y as i32)) < self.fill_chance;
+            }
+        }
+
+        for pass in 0..self.passes {
+            let mut next = walls.clone();
+            let valid = (pass + 1)..(size - pass - 1);
+            for (x, column) in next
+                .iter_mut()
+                .enumerate()
+                .take(valid.end)
+                .skip(valid.start)
+            {
+                for (y, next_wall) in column
+                    .iter_mut()
+                    .enumerate()
+                    .take(valid.end)
+                    .skip(valid.start)
+                {
+                    let count = CaveGenerator::wall_neighbours(&walls, x, y)
+                        .iter()
+                        .filter(|wall| **wall)
+                        .count();
+                    // Become a wall with 5 or more walls around, stay one with 4
+                    *next_wall = count >= 5 || (walls[x][y] && count == 4);
+                }
+            }
+            walls = next;
+        }
+
+        for (x, column) in tiles.iter_mut().enumerate() {
+            for (y, tile) in column.iter_mut().enumerate() {
+                let (wx, wy) = (x + margin, y + margin);
+                *tile = if walls[wx][wy] {
+                    Tile::cave_floor().with_wall(CaveGenerator::wall_neighbours(&walls, wx, wy))
+                } else {
+                    Tile::cave_floor()
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::generation::tests::assert_border_agrees;
+
+    #[test]
+    fn margin_makes_chunk_borders_agree() {
+        let generator = CaveGenerator::new(9);
+        for chunk_pos in [pos!(0, 0), pos!(-1, -1), pos!(3, -5)] {
+            for side in [pos!(1, 0), pos!(-1, 0), pos!(0, 1), pos!(0, -1)] {
+                assert_border_agrees(&generator, chunk_pos, side);
+            }
+        }
+    }
+
+    #[test]
+    fn caves_are_not_solid() {
+        let generator = CaveGenerator::new(9);
+        let mut tiles = [[Tile::empty(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        generator.generate(pos!(0, 0), &mut tiles);
+        let open = tiles
+            .iter()
+            .flatten()
+            .filter(|tile| !tile.blocked())
+            .count();
+        assert!(open > 0 && open < (Chunk::SIZE * Chunk::SIZE) as usize);
+    }
+}
@@ -0,0 +1,337 @@
+use hashbrown::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::world::chunk::{Chunk, Tiles};
+use crate::world::direction::DIRECTIONS;
+use crate::world::generation::{hash_tile, WorldGenerator};
+use crate::world::position::ChunkPosition;
+use crate::world::tile::TileId;
+use crate::{pos, Position, Tile};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Cell {
+    Wall,
+    Floor,
+    Door,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Room {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Room {
+    fn center(&self) -> Position {
+        pos!(self.x + self.w / 2, self.y + self.h / 2)
+    }
+
+    fn contains(&self, pos: Position) -> bool {
+        pos.x >= self.x && pos.y >= self.y && pos.x < self.x + self.w && pos.y < self.y + self.h
+    }
+}
+
+/// Layout of a single sector in sector local tile coordinates
+struct Sector {
+    cells: Vec<Vec<Cell>>,
+    rooms: Vec<Room>,
+}
+
+impl Sector {
+    fn in_room(&self, pos: Position) -> bool {
+        self.rooms.iter().any(|room| room.contains(pos))
+    }
+
+    fn carve_room(&mut self, room: Room) {
+        for x in room.x..(room.x + room.w) {
+            for y in room.y..(room.y + room.h) {
+                self.cells[x as usize][y as usize] = Cell::Floor;
+            }
+        }
+    }
+
+    /// Carves an L shaped corridor, placing doors on the corridor tiles right outside of rooms
+    fn carve_corridor(&mut self, from: Position, to: Position, horizontal_first: bool) {
+        let corner = if horizontal_first {
+            pos!(to.x, from.y)
+        } else {
+            pos!(from.x, to.y)
+        };
+
+        let mut path = Vec::new();
+        for (start, end) in [(from, corner), (corner, to)] {
+            let step = (end - start).sgn();
+            let mut pos = start;
+            while pos != end {
+                path.push(pos);
+                pos = pos + step;
+            }
+        }
+        path.push(to);
+
+        for i in 0..path.len() {
+            let pos = path[i];
+            let cell = &self.cells[pos.x as usize][pos.y as usize];
+            if *cell != Cell::Wall || self.in_room(pos) {
+                continue;
+            }
+
+            let leaving = i > 0 && self.in_room(path[i - 1]);
+            let entering = i + 1 < path.len() && self.in_room(path[i + 1]);
+            self.cells[pos.x as usize][pos.y as usize] = if leaving || entering {
+                Cell::Door
+            } else {
+                Cell::Floor
+            };
+        }
+    }
+}
+
+/// Classic roguelike dungeon of rooms joined by corridors.
+///
+/// The world is split into sectors of `SECTOR_SIZE` tiles, each laid out independently with binary space
+/// partitioning. Sibling partitions are always joined by a corridor, so every room of a sector is connected.
+/// Neighbouring sectors are joined through a gate on their shared edge, whose position both sectors derive
+/// from the seed, so rooms and corridors line up no matter which chunk is generated first.
+#[derive(Debug, Clone)]
+pub struct DungeonGenerator {
+    seed: i32,
+    /// Size in sectors of a finite dungeon starting at sector 0, 0. Infinite when `None`
+    pub size: Option<Position>,
+}
+
+impl DungeonGenerator {
+    pub const SECTOR_SIZE: i32 = 48;
+    const MIN_LEAF: i32 = 10;
+    const MAX_LEAF: i32 = 20;
+    const MIN_ROOM: i32 = 4;
+
+    pub fn new(seed: i32, size: Option<Position>) -> Self {
+        DungeonGenerator { seed, size }
+    }
+
+    fn in_bounds(&self, sector_pos: Position) -> bool {
+        match self.size {
+            Some(size) => {
+                sector_pos.x >= 0
+                    && sector_pos.y >= 0
+                    && sector_pos.x < size.x
+                    && sector_pos.y < size.y
+            }
+            None => true,
+        }
+    }
+
+    /// Offset along the edge of the gate between `sector_pos` and its east (`vertical`) or south neighbour
+    fn gate_offset(&self, sector_pos: Position, vertical: bool) -> i32 {
+        let salt = if vertical { 1 } else { 2 };
+        let hash = hash_tile(self.seed.wrapping_add(salt), sector_pos);
+        2 + (hash % (DungeonGenerator::SECTOR_SIZE - 4) as u64) as i32
+    }
+
+    /// Splits `area` recursively, returning the center of a room in it that corridors connect to
+    fn partition(
+        &self,
+        rng: &mut StdRng,
+        area: Room,
+        rooms: &mut Vec<Room>,
+        connections: &mut Vec<(Position, Position)>,
+    ) -> Position {
+        let split_x = if area.w != area.h {
+            area.w > area.h
+        } else {
+            rng.gen_bool(0.5)
+        };
+        let length = if split_x { area.w } else { area.h };
+
+        let is_leaf = (area.w <= DungeonGenerator::MAX_LEAF
+            && area.h <= DungeonGenerator::MAX_LEAF)
+            || length < DungeonGenerator::MIN_LEAF * 2;
+        if is_leaf {
+            let w = rng.gen_range(DungeonGenerator::MIN_ROOM..=area.w - 2);
+            let h = rng.gen_range(DungeonGenerator::MIN_ROOM..=area.h - 2);
+            let room = Room {
+                x: area.x + rng.gen_range(1..=area.w - w - 1),
+                y: area.y + rng.gen_range(1..=area.h - h - 1),
+                w,
+                h,
+            };
+            rooms.push(room);
+            return room.center();
+        }
+
+        let split = rng.gen_range(DungeonGenerator::MIN_LEAF..=length - DungeonGenerator::MIN_LEAF);
+        let (a, b) = if split_x {
+            (
+                Room { w: split, ..area },
+                Room {
+                    x: area.x + split,
+                    w: area.w - split,
+                    ..area
+                },
+            )
+        } else {
+            (
+                Room { h: split, ..area },
+                Room {
+                    y: area.y + split,
+                    h: area.h - split,
+                    ..area
+                },
+            )
+        };
+
+        let center_a = self.partition(rng, a, rooms, connections);
+        let center_b = self.partition(rng, b, rooms, connections);
+        connections.push((center_a, center_b));
+
+        if rng.gen_bool(0.5) {
+            center_a
+        } else {
+            center_b
+        }
+    }
+
+    fn build_sector(&self, sector_pos: Position) -> Sector {
+        let size = DungeonGenerator::SECTOR_SIZE;
+        let mut rng = StdRng::seed_from_u64(hash_tile(self.seed, sector_pos));
+
+        // The outer ring of every sector stays solid apart from the gates
+        let area = Room {
+            x: 1,
+            y: 1,
+            w: size - 2,
+            h: size - 2,
+        };
+        let mut rooms = Vec::new();
+        let mut connections = Vec::new();
+        let hub = self.partition(&mut rng, area, &mut rooms, &mut connections);
+
+        let mut sector = Sector {
+            cells: vec![vec![Cell::Wall; size as usize]; size as usize],
+            rooms,
+        };
+
+        // Carve every room before the corridors so doors are only placed at real room entrances
+        for room in sector.rooms.clone() {
+            sector.carve_room(room);
+        }
+        for (from, to) in connections {
+            let horizontal_first = rng.gen_bool(0.5);
+            sector.carve_corridor(from, to, horizontal_first);
+        }
+
+        let edge = size - 1;
+        let gates = [
+            (pos!(1, 0), pos!(edge, self.gate_offset(sector_pos, true))),
+            (
+                pos!(-1, 0),
+                pos!(0, self.gate_offset(sector_pos + pos!(-1, 0), true)),
+            ),
+            (pos!(0, 1), pos!(self.gate_offset(sector_pos, false), edge)),
+            (
+                pos!(0, -1),
+                pos!(self.gate_offset(sector_pos + pos!(0, -1), false), 0),
+            ),
+        ];
+        for (neighbour, gate) in gates {
+            if self.in_bounds(sector_pos + neighbour) {
+                // Leave the hub along the edge's direction last, so only the gate touches the outer ring
+                sector.carve_corridor(hub, gate, neighbour.y != 0);
+            }
+        }
+
+        sector
+    }
+}
+
+impl WorldGenerator for DungeonGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        let size = DungeonGenerator::SECTOR_SIZE;
+        let chunk_tile_pos = ChunkPosition::to_tile(chunk_pos);
+        let mut sectors = HashMap::<Position, Option<Sector>>::new();
+
+        let mut cell_at = |tile_pos: Position| -> Cell {
+            let sector_pos = pos!(tile_pos.x.div_euclid(size), tile_pos.y.div_euclid(size));
+            let sector = sectors.entry(sector_pos).or_insert_with(|| {
+                if self.in_bounds(sector_pos) {
+                    Some(self.build_sector(sector_pos))
+                } else {
+                    None
+                }
+            });
+            match sector {
+                Some(sector) => {
+                    sector.cells[tile_pos.x.rem_euclid(size) as usize]
+                        [tile_pos.y.rem_euclid(size) as usize]
+                }
+                None => Cell::Wall,
+            }
+        };
+
+        for x in 0..Chunk::SIZE {
+            for y in 0..Chunk::SIZE {
+                let tile_pos = chunk_tile_pos + pos!(x, y);
+                tiles[x as usize][y as usize] = match cell_at(tile_pos) {
+                    Cell::Wall => Tile::cave_floor()
+                        .with_wall(DIRECTIONS.map(|dir| cell_at(tile_pos + dir) == Cell::Wall)),
+                    Cell::Floor => Tile::cave_floor(),
+                    Cell::Door => Tile::cave_floor().with_object(TileId::door()),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::generation::tests::assert_border_agrees;
+
+    /// Chunk borders that are also sector borders, sectors are 48 and chunks 32 tiles wide
+    const SECTOR_BORDER_CHUNK: i32 = 3;
+
+    #[test]
+    fn gates_agree_across_chunk_borders() {
+        let generator = DungeonGenerator::new(5, None);
+        let west = pos!(SECTOR_BORDER_CHUNK - 1, 0);
+        let north = pos!(0, SECTOR_BORDER_CHUNK - 1);
+        assert_eq!(
+            ChunkPosition::to_tile(west + pos!(1, 0)).x % DungeonGenerator::SECTOR_SIZE,
+            0
+        );
+
+        for (chunk_pos, side) in [(west, pos!(1, 0)), (north, pos!(0, 1))] {
+            // Covers the gates of two sectors along the border
+            let mut gates = 0;
+            for i in 0..SECTOR_BORDER_CHUNK {
+                let along = if side.x != 0 { pos!(0, i) } else { pos!(i, 0) };
+                let from = assert_border_agrees(&generator, chunk_pos + along, side);
+                let to = assert_border_agrees(&generator, chunk_pos + along + side, side * -1);
+                assert_eq!(
+                    from.iter()
+                        .map(|tile_pos| *tile_pos + side)
+                        .collect::<Vec<_>>(),
+                    to,
+                    "Gates of chunks {:?} and {:?} do not line up",
+                    chunk_pos + along,
+                    chunk_pos + along + side
+                );
+                gates += from.len();
+            }
+            assert_eq!(gates, 2);
+        }
+    }
+
+    #[test]
+    fn finite_dungeons_have_no_gates_outwards() {
+        let generator = DungeonGenerator::new(5, Some(pos!(1, 1)));
+        for i in 0..2 {
+            assert!(assert_border_agrees(&generator, pos!(1, i), pos!(1, 0)).is_empty());
+            assert!(assert_border_agrees(&generator, pos!(i, 1), pos!(0, 1)).is_empty());
+        }
+    }
+}
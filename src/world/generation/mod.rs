@@ -0,0 +1,239 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::chunk::Tiles;
+use crate::world::generation::biome::BiomeGenerator;
+use crate::world::generation::cave::CaveGenerator;
+use crate::world::generation::dungeon::DungeonGenerator;
+use crate::world::generation::perlin::PerlinGenerator;
+use crate::world::generation::structure::StructureGenerator;
+use crate::Position;
+
+pub mod biome;
+pub mod cave;
+pub mod dungeon;
+pub mod perlin;
+pub mod structure;
+
+/// Produces the terrain of chunks.
+///
+/// Generators are run on the chunk worker threads and have to be deterministic,
+/// the same chunk position must always produce the same tiles.
+pub trait WorldGenerator: Debug + Send + Sync {
+    /// Writes the terrain of the chunk at `chunk_pos` into `tiles`.
+    /// `tiles` may already contain the output of previous generators when layered.
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles);
+}
+
+/// Deterministic hash of a tile position, used where generators need per tile randomness
+pub fn hash_tile(seed: i32, tile_pos: Position) -> u64 {
+    // SplitMix64 finalizer over the packed seed and coordinates
+    let mut z = (seed as u32 as u64) << 32 ^ (tile_pos.x as u32 as u64);
+    z = z
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .wrapping_add(tile_pos.y as u32 as u64);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Deterministic value in `[0, 1)` for a tile position
+pub fn tile_random(seed: i32, tile_pos: Position) -> f64 {
+    (hash_tile(seed, tile_pos) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Runs several generators in order, each one building on the output of the previous ones
+#[derive(Debug)]
+pub struct LayeredGenerator {
+    pub layers: Vec<Box<dyn WorldGenerator>>,
+}
+
+impl LayeredGenerator {
+    pub fn new(layers: Vec<Box<dyn WorldGenerator>>) -> Self {
+        LayeredGenerator { layers }
+    }
+}
+
+impl WorldGenerator for LayeredGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        for layer in &self.layers {
+            layer.generate(chunk_pos, tiles);
+        }
+    }
+}
+
+/// Serializable description of a generator, stored with the level so saves regenerate the same terrain
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum GeneratorKind {
+    #[default]
+    Perlin,
+    Biomes,
+    Caves,
+    /// Rooms and corridors, `size` limits the dungeon to that many sectors
+    Dungeon {
+        size: Option<Position>,
+    },
+    /// Prefabs stamped over the previous layers
+    Structures,
+    Layered(Vec<GeneratorKind>),
+}
+
+impl GeneratorKind {
+    pub fn build(&self, seed: i32) -> Box<dyn WorldGenerator> {
+        match self {
+            GeneratorKind::Perlin => Box::new(PerlinGenerator::new(seed)),
+            GeneratorKind::Biomes => Box::new(BiomeGenerator::new(seed)),
+            GeneratorKind::Caves => Box::new(CaveGenerator::new(seed)),
+            GeneratorKind::Dungeon { size } => Box::new(DungeonGenerator::new(seed, *size)),
+            GeneratorKind::Structures => Box::new(StructureGenerator::new(seed)),
+            GeneratorKind::Layered(layers) => Box::new(LayeredGenerator::new(
+                layers.iter().map(|layer| layer.build(seed)).collect(),
+            )),
+        }
+    }
+
+    pub fn build_shared(&self, seed: i32) -> Arc<dyn WorldGenerator> {
+        Arc::from(self.build(seed))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use hashbrown::HashMap;
+
+    use super::*;
+    use crate::pos;
+    use crate::world::chunk::Chunk;
+    use crate::world::direction::DIRECTIONS;
+    use crate::world::position::{ChunkPosition, TilePosition};
+    use crate::world::tile::TileType;
+    use crate::Tile;
+
+    pub fn generate_chunk(generator: &dyn WorldGenerator, chunk_pos: Position) -> Tiles {
+        let mut tiles = [[Tile::empty(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+        generator.generate(chunk_pos, &mut tiles);
+        tiles
+    }
+
+    /// Checks the chunk at `chunk_pos` and its neighbour at `chunk_pos + side` agree on their border:
+    /// walls along it are connected exactly towards the walls across it.
+    /// Returns the tiles of the border of `chunk_pos` that are passable.
+    pub fn assert_border_agrees(
+        generator: &dyn WorldGenerator,
+        chunk_pos: Position,
+        side: Position,
+    ) -> Vec<Position> {
+        let origin = ChunkPosition::to_tile(chunk_pos);
+        let tiles = HashMap::<Position, Tiles>::from_iter([
+            (chunk_pos, generate_chunk(generator, chunk_pos)),
+            (
+                chunk_pos + side,
+                generate_chunk(generator, chunk_pos + side),
+            ),
+        ]);
+        let tile_at = |tile_pos: Position| {
+            tiles.get(&TilePosition::to_chunk(tile_pos)).map(|chunk| {
+                let in_chunk = TilePosition::to_tile_in_chunk(tile_pos);
+                chunk[in_chunk.x as usize][in_chunk.y as usize]
+            })
+        };
+
+        let last = Chunk::SIZE - 1;
+        let (first, along) = if side.x != 0 {
+            (pos!(if side.x > 0 { last } else { 0 }, 0), pos!(0, 1))
+        } else {
+            (pos!(0, if side.y > 0 { last } else { 0 }), pos!(1, 0))
+        };
+
+        let mut passable = Vec::new();
+        for i in 0..Chunk::SIZE {
+            let border = origin + first + along * i;
+            for tile_pos in [border, border + side] {
+                let tile = tile_at(tile_pos).unwrap();
+                if tile.tile_type() != TileType::Wall {
+                    continue;
+                }
+                for (bit, dir) in DIRECTIONS.iter().enumerate() {
+                    if let Some(neighbour) = tile_at(tile_pos + *dir) {
+                        assert_eq!(
+                            tile.connections & (1 << bit) != 0,
+                            neighbour.tile_type() == TileType::Wall,
+                            "Wall at {:?} disagrees with its neighbour at {:?}",
+                            tile_pos,
+                            tile_pos + *dir
+                        );
+                    }
+                }
+            }
+            if !tile_at(border).unwrap().blocked() {
+                passable.push(border);
+            }
+        }
+        passable
+    }
+
+    fn kinds() -> Vec<GeneratorKind> {
+        vec![
+            GeneratorKind::Perlin,
+            GeneratorKind::Biomes,
+            GeneratorKind::Caves,
+            GeneratorKind::Dungeon { size: None },
+            GeneratorKind::Dungeon {
+                size: Some(pos!(2, 2)),
+            },
+            GeneratorKind::Structures,
+            GeneratorKind::Layered(vec![GeneratorKind::Biomes, GeneratorKind::Structures]),
+        ]
+    }
+
+    #[test]
+    fn generators_are_deterministic_per_seed() {
+        let chunks = [
+            pos!(0, 0),
+            pos!(1, 0),
+            pos!(-1, -2),
+            pos!(5, -3),
+            pos!(-7, 4),
+        ];
+        for kind in kinds() {
+            let a = kind.build(42);
+            let b = kind.build(42);
+            // Chunks are generated in any order by the worker threads
+            for (chunk_pos, other_pos) in chunks.iter().zip(chunks.iter().rev()) {
+                let first = generate_chunk(&*a, *chunk_pos);
+                generate_chunk(&*b, *other_pos);
+                assert_eq!(
+                    first,
+                    generate_chunk(&*b, *chunk_pos),
+                    "{:?} differs at chunk {:?}",
+                    kind,
+                    chunk_pos
+                );
+                assert_eq!(first, generate_chunk(&*a, *chunk_pos));
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_change_the_terrain() {
+        for kind in [
+            GeneratorKind::Perlin,
+            GeneratorKind::Biomes,
+            GeneratorKind::Caves,
+            GeneratorKind::Dungeon { size: None },
+        ] {
+            let a = kind.build(1);
+            let b = kind.build(2);
+            assert!(
+                [pos!(0, 0), pos!(3, -2), pos!(-4, 1)]
+                    .iter()
+                    .any(|chunk_pos| generate_chunk(&*a, *chunk_pos)
+                        != generate_chunk(&*b, *chunk_pos)),
+                "{:?} ignores its seed",
+                kind
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use noise::{NoiseFn, Perlin};
+
+use crate::world::chunk::{Chunk, Tiles};
+use crate::world::generation::WorldGenerator;
+use crate::world::tile::TileId;
+use crate::Position;
+
+/// Scatters rock wherever a single Perlin noise layer exceeds the threshold
+#[derive(Debug, Clone)]
+pub struct PerlinGenerator {
+    perlin: Perlin,
+    pub scale: f64,
+    pub threshold: f64,
+}
+
+impl PerlinGenerator {
+    pub const SCALE: f64 = 15.0;
+    pub const THRESHOLD: f64 = 0.5;
+
+    pub fn new(seed: i32) -> Self {
+        PerlinGenerator {
+            perlin: Perlin::new(seed as u32),
+            scale: PerlinGenerator::SCALE,
+            threshold: PerlinGenerator::THRESHOLD,
+        }
+    }
+}
+
+impl WorldGenerator for PerlinGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        for y in 0..Chunk::SIZE {
+            for x in 0..Chunk::SIZE {
+                let val = self.perlin.get([
+                    (x + chunk_pos.x * Chunk::SIZE) as f64 / self.scale,
+                    (y + chunk_pos.y * Chunk::SIZE) as f64 / self.scale,
+                ]);
+
+                if val > self.threshold {
+                    tiles[x as usize][y as usize].object = Some(TileId::rock());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::world::chunk::{Chunk, Tiles};
+use crate::world::direction::DIRECTIONS;
+use crate::world::generation::{hash_tile, WorldGenerator};
+use crate::world::position::ChunkPosition;
+use crate::world::tile::TileId;
+use crate::{pos, Position, Tile};
+
+/// A hand made arrangement of tiles.
+///
+/// Legend: `#` wall, `.` floor, `+` door, `r` rubble, `T` tree, `i` torch, space keeps the underlying
+/// terrain. Walls, rubble, trees and torches are placed on the underlying floor.
+pub struct Prefab {
+    pub name: &'static str,
+    pub rows: &'static [&'static str],
+}
+
+impl Prefab {
+    pub fn width(&self) -> i32 {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    fn char_at(&self, x: i32, y: i32) -> char {
+        if x < 0 || y < 0 || y >= self.height() {
+            return ' ';
+        }
+        self.rows[y as usize]
+            .as_bytes()
+            .get(x as usize)
+            .map(|c| *c as char)
+            .unwrap_or(' ')
+    }
+
+    fn stamp_tile(&self, x: i32, y: i32, tile: &mut Tile) {
+        match self.char_at(x, y) {
+            '#' => {
+                *tile =
+                    tile.with_wall(DIRECTIONS.map(|dir| self.char_at(x + dir.x, y + dir.y) == '#'))
+            }
+            '.' => *tile = Tile::grass(),
+            '+' => *tile = Tile::grass().with_object(TileId::door()),
+            'r' => tile.object = Some(TileId::rock()),
+            'T' => tile.object = Some(TileId::tree()),
+            'i' => tile.object = Some(TileId::torch()),
+            _ => {}
+        }
+    }
+}
+
+pub const PREFABS: [Prefab; 3] = [
+    Prefab {
+        name: "ruins",
+        rows: &[
+            "######## ######      ####   ##########",
+            "#......#      #         #   #........#",
+            "#......# r    #  rr     #   #........#",
+            "#......        r        #   #...r....#",
+            "#...r..#                    +........#",
+            "##+#####     ##### ##         r......#",
+            "             #.......#    rr  #......#",
+            "   r    r    #...r...#        ##+#####",
+            "             #.......+   r",
+            "#####  ####  ####  ###      ###   ####",
+            "#.........r...........#  r  #       #",
+            "#..........r..........+     #   r   #",
+            "#.....................#     #       #",
+            "####+######  ##########     ####+####",
+        ],
+    },
+    Prefab {
+        name: "hut",
+        rows: &[
+            "#########",
+            "#.......#",
+            "#i.....i#",
+            "#.......#",
+            "####+####",
+        ],
+    },
+    Prefab {
+        name: "shrine",
+        rows: &[
+            "  #######",
+            " ##i...i##",
+            "##.......##",
+            "#....r....#",
+            "#...rTr...#",
+            "+....r....+",
+            "#.........#",
+            "##.......##",
+            " ##.....##",
+            "  ###+###",
+        ],
+    },
+];
+
+/// Stamps prefabs over the terrain of previous generators.
+///
+/// The world is divided into placement cells of `cell_size` tiles, each holding at most one prefab that
+/// fits entirely within the cell. Whether a cell has a prefab, which one and where is derived from the seed
+/// and the cell position only, so every chunk a prefab overlaps stamps its part of it consistently.
+#[derive(Debug, Clone)]
+pub struct StructureGenerator {
+    seed: i32,
+    pub cell_size: i32,
+    /// Chance of a cell containing a prefab
+    pub chance: f64,
+}
+
+/// A prefab placed in the world at `pos`, its top left tile
+pub struct Placement {
+    pub prefab: &'static Prefab,
+    pub pos: Position,
+}
+
+impl StructureGenerator {
+    pub const CELL_SIZE: i32 = 64;
+    pub const CHANCE: f64 = 0.35;
+
+    pub fn new(seed: i32) -> Self {
+        StructureGenerator {
+            seed,
+            cell_size: StructureGenerator::CELL_SIZE,
+            chance: StructureGenerator::CHANCE,
+        }
+    }
+
+    pub fn placement(&self, cell_pos: Position) -> Option<Placement> {
+        // Salted so structures don't correlate with other generators hashing the same seed
+        let mut rng = StdRng::seed_from_u64(hash_tile(self.seed.wrapping_add(8), cell_pos));
+        if !rng.gen_bool(self.chance) {
+            return None;
+        }
+
+        let prefab = &PREFABS[rng.gen_range(0..PREFABS.len())];
+        let max_x = (self.cell_size - prefab.width()).max(0);
+        let max_y = (self.cell_size - prefab.height()).max(0);
+        let offset = pos!(rng.gen_range(0..=max_x), rng.gen_range(0..=max_y));
+
+        Some(Placement {
+            prefab,
+            pos: cell_pos * self.cell_size + offset,
+        })
+    }
+
+    fn stamp(&self, placement: &Placement, chunk_tile_pos: Position, tiles: &mut Tiles) {
+        let start = pos!(
+            placement.pos.x.max(chunk_tile_pos.x),
+            placement.pos.y.max(chunk_tile_pos.y)
+        );
+        let end = pos!(
+            (placement.pos.x + placement.prefab.width()).min(chunk_tile_pos.x + Chunk::SIZE),
+            (placement.pos.y + placement.prefab.height()).min(chunk_tile_pos.y + Chunk::SIZE)
+        );
+
+        for x in start.x..end.x {
+            for y in start.y..end.y {
+                let prefab_pos = pos!(x, y) - placement.pos;
+                let chunk_pos = pos!(x, y) - chunk_tile_pos;
+                placement.prefab.stamp_tile(
+                    prefab_pos.x,
+                    prefab_pos.y,
+                    &mut tiles[chunk_pos.x as usize][chunk_pos.y as usize],
+                );
+            }
+        }
+    }
+}
+
+impl WorldGenerator for StructureGenerator {
+    fn generate(&self, chunk_pos: Position, tiles: &mut Tiles) {
+        let chunk_tile_pos = ChunkPosition::to_tile(chunk_pos);
+        let last_tile_pos = chunk_tile_pos + (Chunk::SIZE - 1);
+
+        // Prefabs never leave their cell, so only cells overlapping the chunk can affect it
+        for cell_x in
+            chunk_tile_pos.x.div_euclid(self.cell_size)..=last_tile_pos.x.div_euclid(self.cell_size)
+        {
+            for cell_y in chunk_tile_pos.y.div_euclid(self.cell_size)
+                ..=last_tile_pos.y.div_euclid(self.cell_size)
+            {
+                if let Some(placement) = self.placement(pos!(cell_x, cell_y)) {
+                    self.stamp(&placement, chunk_tile_pos, tiles);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use hashbrown::HashMap;
+
+use crate::world::chunk::UnloadedChunk;
+use crate::Position;
+
+/// Recently unloaded chunks kept in memory, so walking back into them does not touch the disk.
+/// Once the chunks use more memory than the budget the least recently unloaded ones are evicted,
+/// down to `HotChunks::EVICT_TARGET` of the budget so evicted chunks are written in batches.
+#[derive(Debug)]
+pub struct HotChunks {
+    chunks: HashMap<Position, (u64, UnloadedChunk)>,
+    /// Positions of the chunks by the tick they were inserted at, oldest first
+    order: BTreeMap<u64, Position>,
+    tick: u64,
+    size: usize,
+    /// Memory budget in bytes
+    pub budget: usize,
+}
+
+impl Default for HotChunks {
+    fn default() -> Self {
+        HotChunks::new(HotChunks::DEFAULT_BUDGET)
+    }
+}
+
+impl HotChunks {
+    pub const DEFAULT_BUDGET: usize = 8 * 1024 * 1024;
+    /// Share of the budget in percent that eviction frees memory down to
+    pub const EVICT_TARGET: usize = 75;
+
+    pub fn new(budget: usize) -> Self {
+        HotChunks {
+            chunks: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            budget,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Memory used by the chunks in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, chunk_pos: Position) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    /// Inserts the chunk as the most recently used one, replacing any chunk at the same position
+    pub fn insert(&mut self, chunk: UnloadedChunk) {
+        self.take(chunk.pos);
+
+        self.tick += 1;
+        self.size += chunk.size_in_bytes();
+        self.order.insert(self.tick, chunk.pos);
+        self.chunks.insert(chunk.pos, (self.tick, chunk));
+    }
+
+    pub fn take(&mut self, chunk_pos: Position) -> Option<UnloadedChunk> {
+        let (tick, chunk) = self.chunks.remove(&chunk_pos)?;
+        self.order.remove(&tick);
+        self.size -= chunk.size_in_bytes();
+        Some(chunk)
+    }
+
+    /// Removes least recently used chunks once the memory used is over the budget, until it is within
+    /// `EVICT_TARGET` of the budget
+    pub fn evict(&mut self) -> Vec<UnloadedChunk> {
+        let mut evicted = Vec::new();
+        if self.size <= self.budget {
+            return evicted;
+        }
+        let target = self.budget / 100 * HotChunks::EVICT_TARGET;
+        while self.size > target {
+            let chunk_pos = match self.order.values().next() {
+                Some(chunk_pos) => *chunk_pos,
+                None => break,
+            };
+            evicted.extend(self.take(chunk_pos));
+        }
+        evicted
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &UnloadedChunk> {
+        self.chunks.values().map(|(_, chunk)| chunk)
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut UnloadedChunk> {
+        self.chunks.values_mut().map(|(_, chunk)| chunk)
+    }
+}
@@ -1,11 +1,23 @@
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use ggez::GameResult;
+use specs::shrev::EventChannel;
 
+use crate::world::chunk::Chunk;
+use crate::world::chunk_pool::ChunkPool;
+use crate::world::compressed_tiles::CompressedTiles;
 use crate::world::direction::{DirectionalMap, DIRECTIONS};
-use crate::world::position::TilePosition;
-use crate::{Camera, Rusted, SpriteAtlas};
+use crate::world::generation::{GeneratorKind, WorldGenerator};
+use crate::world::hot_chunks::HotChunks;
+use crate::world::light::LightMap;
+use crate::world::line::Line;
+use crate::world::position::{ChunkPosition, TilePosition};
+use crate::world::region::RegionStorage;
+use crate::world::tile::TileType;
+use crate::{pos, Camera, Rusted, SpriteAtlas};
 
 use super::{
     chunk::{LoadedChunk, UnloadedChunk},
@@ -13,68 +25,461 @@ use super::{
     tile::Tile,
 };
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkState {
+    Unloaded,
+    /// Generation was requested and is running on a worker thread
+    Pending,
+    Loaded,
+    /// The stored copy could not be decoded, such as when it uses unknown tile ids. The chunk stays
+    /// unloaded and blocked so the stored copy is never replaced by a generated one
+    Failed,
+}
+
+/// Emitted through `Level::tile_events` whenever a tile is replaced
+#[derive(Debug, Copy, Clone)]
+pub struct TileChanged {
+    pub tile_pos: Position,
+    pub previous: Tile,
+    pub tile: Tile,
+}
+
+/// Emitted through `Level::chunk_events` whenever a chunk is installed or unloaded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkEvent {
+    Loaded(Position),
+    Unloaded(Position),
+}
+
 #[derive(Debug)]
 pub struct Level {
-    pub unloaded_chunks: HashMap<Position, UnloadedChunk>,
     pub loaded_chunks: HashMap<Position, LoadedChunk>,
+    pub pending_chunks: HashSet<Position>,
+    /// Chunks whose stored copy could not be decoded, see `ChunkState::Failed`
+    pub failed_chunks: HashSet<Position>,
+    /// Unloaded chunks kept in memory, written to disk when evicted
+    pub hot_chunks: HotChunks,
+    /// Serialized entities of chunks loaded from disk, waiting to be spawned into the world
+    pub restored_entities: Vec<(Position, Vec<u8>)>,
+    /// Edits to pending chunks, applied once their generation finishes
+    pub pending_edits: HashMap<Position, Vec<(Position, Tile)>>,
+    pub tile_events: EventChannel<TileChanged>,
+    pub chunk_events: EventChannel<ChunkEvent>,
+    pub pool: ChunkPool,
+    pub storage: RegionStorage,
+    pub dir: PathBuf,
     pub seed: i32,
+    pub generator_kind: GeneratorKind,
+    pub generator: Arc<dyn WorldGenerator>,
 }
 
 impl Default for Level {
     fn default() -> Self {
-        Level {
-            unloaded_chunks: HashMap::new(),
-            loaded_chunks: HashMap::new(),
-            seed: 0,
-        }
+        Level::new(0, GeneratorKind::default())
     }
 }
 
 impl Level {
-    pub fn new(seed: i32) -> Self {
+    pub const SAVE_DIR: &'static str = "saves";
+
+    pub fn new(seed: i32, generator_kind: GeneratorKind) -> Self {
+        Level::with_dir(seed, generator_kind, Level::default_dir(seed))
+    }
+
+    /// Creates a level stored in `dir`, chunks are kept in region files under `dir/regions`
+    pub fn with_dir<P: Into<PathBuf>>(seed: i32, generator_kind: GeneratorKind, dir: P) -> Self {
+        let dir = dir.into();
+        let generator = generator_kind.build_shared(seed);
         Level {
-            unloaded_chunks: HashMap::new(),
             loaded_chunks: HashMap::new(),
+            pending_chunks: HashSet::new(),
+            failed_chunks: HashSet::new(),
+            hot_chunks: HotChunks::default(),
+            restored_entities: Vec::new(),
+            pending_edits: HashMap::new(),
+            tile_events: EventChannel::new(),
+            chunk_events: EventChannel::new(),
+            pool: ChunkPool::new(Arc::clone(&generator)),
+            storage: RegionStorage::new(dir.join("regions")),
+            dir,
             seed,
+            generator_kind,
+            generator,
         }
     }
 
+    /// Levels are stored per seed, so revisiting a seed resumes the previously explored world
+    pub fn default_dir(seed: i32) -> PathBuf {
+        Path::new(Level::SAVE_DIR).join(format!("world_{}", seed))
+    }
+
     pub fn is_loaded(&self, pos: &Position) -> bool {
         return self.loaded_chunks.contains_key(pos);
     }
 
-    pub fn unload_chunk(&mut self, chunk_pos: Position) -> Option<&UnloadedChunk> {
-        let chunk = self.loaded_chunks.remove(&chunk_pos)?;
-        println!("Unloaded chunk {:?}", chunk_pos);
+    pub fn chunk_state(&self, chunk_pos: Position) -> ChunkState {
+        if self.loaded_chunks.contains_key(&chunk_pos) {
+            ChunkState::Loaded
+        } else if self.pending_chunks.contains(&chunk_pos) {
+            ChunkState::Pending
+        } else if self.failed_chunks.contains(&chunk_pos) {
+            ChunkState::Failed
+        } else {
+            ChunkState::Unloaded
+        }
+    }
+
+    /// Moves the chunk along with the serialized entities that were in it to the hot chunks,
+    /// it is written to disk once evicted by `evict_hot_chunks`
+    pub fn unload_chunk(&mut self, chunk_pos: Position, entities: Vec<u8>) {
+        let chunk = match self.loaded_chunks.remove(&chunk_pos) {
+            Some(chunk) => chunk,
+            None => return,
+        };
 
         //chunk.unpopulate_neighbours();
 
-        self.unloaded_chunks
-            .insert(chunk_pos, chunk.unload(Vec::with_capacity(0)));
-        return self.unloaded_chunks.get(&chunk_pos);
+        self.hot_chunks.insert(chunk.unload(entities));
+        self.chunk_events
+            .single_write(ChunkEvent::Unloaded(chunk_pos));
     }
 
-    pub fn ensure_loaded(&mut self, chunk_pos: Position) -> &LoadedChunk {
-        if !self.is_loaded(&chunk_pos) {
-            println!("Loading chunk {:?}", chunk_pos);
-            return self.load_chunk(chunk_pos);
+    /// Evicts hot chunks once over the memory budget, dirty ones are written to disk.
+    /// Eviction goes below the budget so chunks are written in batches, each region file is rewritten
+    /// once per batch. Chunks that failed to be written are kept hot so they are retried on the next eviction.
+    pub fn evict_hot_chunks(&mut self) -> io::Result<()> {
+        // Clean chunks are identical to their stored or generated copy
+        let dirty = self
+            .hot_chunks
+            .evict()
+            .into_iter()
+            .filter(|chunk| chunk.dirty)
+            .collect::<Vec<UnloadedChunk>>();
+        if dirty.is_empty() {
+            return Ok(());
         }
 
-        return self.loaded_chunks.get(&chunk_pos).unwrap();
+        if let Err(e) = self.storage.write_all(&dirty) {
+            for chunk in dirty {
+                self.hot_chunks.insert(chunk);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes every dirty loaded and hot chunk to disk while keeping them in memory
+    pub fn save_chunks(&mut self) -> io::Result<()> {
+        let snapshots = self
+            .loaded_chunks
+            .values()
+            .filter(|chunk| chunk.dirty)
+            .map(|chunk| chunk.snapshot())
+            .collect::<Vec<UnloadedChunk>>();
+        let hot_chunks = self.hot_chunks.chunks().filter(|chunk| chunk.dirty);
+        self.storage.write_all(snapshots.iter().chain(hot_chunks))?;
+
+        for chunk in self.loaded_chunks.values_mut() {
+            chunk.dirty = false;
+        }
+        for chunk in self.hot_chunks.chunks_mut() {
+            chunk.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn set_tile(&mut self, tile_pos: Position, tile: Tile) -> io::Result<()> {
+        self.set_tiles(std::iter::once((tile_pos, tile)))
+    }
+
+    /// Replaces several tiles at once, emitting a `TileChanged` event for each of them.
+    ///
+    /// Loaded chunks are edited in place and marked dirty, pending chunks get the edits once generated.
+    /// Unloaded chunks are taken from the hot chunks or read from disk, then edited and kept hot. Chunks
+    /// that were never stored are requested from the worker pool and get the edits once generated.
+    pub fn set_tiles<I: IntoIterator<Item = (Position, Tile)>>(
+        &mut self,
+        edits: I,
+    ) -> io::Result<()> {
+        let mut unloaded_edits = HashMap::<Position, Vec<(Position, Tile)>>::new();
+        let mut changes = Vec::new();
+        let mut result = Ok(());
+
+        for (tile_pos, tile) in edits {
+            let chunk_pos = TilePosition::to_chunk(tile_pos);
+            match self.chunk_state(chunk_pos) {
+                ChunkState::Loaded => {
+                    let chunk = self.loaded_chunks.get_mut(&chunk_pos).unwrap();
+                    let previous =
+                        chunk.set_tile_at(TilePosition::to_tile_in_chunk(tile_pos), tile);
+                    changes.push(TileChanged {
+                        tile_pos,
+                        previous,
+                        tile,
+                    });
+                }
+                ChunkState::Pending => self
+                    .pending_edits
+                    .entry(chunk_pos)
+                    .or_default()
+                    .push((tile_pos, tile)),
+                ChunkState::Unloaded => unloaded_edits
+                    .entry(chunk_pos)
+                    .or_default()
+                    .push((tile_pos, tile)),
+                ChunkState::Failed => {
+                    if result.is_ok() {
+                        result = Err(Level::failed_error(chunk_pos));
+                    }
+                }
+            }
+        }
+
+        // Chunks that could not be read are left alone, their edits are dropped and the first error returned
+        for (chunk_pos, edits) in unloaded_edits {
+            let mut unloaded_chunk = match self.take_unloaded_chunk(chunk_pos) {
+                Ok(Some(unloaded_chunk)) => unloaded_chunk,
+                Ok(None) => {
+                    self.pending_chunks.insert(chunk_pos);
+                    self.pool.request(chunk_pos);
+                    self.pending_edits
+                        .entry(chunk_pos)
+                        .or_default()
+                        .extend(edits);
+                    continue;
+                }
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                    continue;
+                }
+            };
+            let mut tiles = unloaded_chunk.tiles.decompress();
+            for (tile_pos, tile) in edits {
+                let tile_chunk_pos = TilePosition::to_tile_in_chunk(tile_pos);
+                let previous = std::mem::replace(
+                    &mut tiles[tile_chunk_pos.x as usize][tile_chunk_pos.y as usize],
+                    tile,
+                );
+                changes.push(TileChanged {
+                    tile_pos,
+                    previous,
+                    tile,
+                });
+            }
+            unloaded_chunk.tiles = CompressedTiles::compress(&tiles);
+            unloaded_chunk.dirty = true;
+            self.hot_chunks.insert(unloaded_chunk);
+        }
+
+        for change in &changes {
+            self.autotile_around(change.tile_pos);
+        }
+        self.tile_events.iter_write(changes);
+        let evicted = self.evict_hot_chunks();
+        result.and(evicted)
     }
 
-    pub fn load_chunk(&mut self, chunk_pos: Position) -> &LoadedChunk {
+    /// Makes sure the chunk gets loaded without blocking.
+    /// Hot and stored chunks are installed right away, missing chunks are generated on the worker pool
+    /// and stay pending until installed by `install_generated_chunks`.
+    /// Chunks that are stored but could not be read stay unloaded instead of being generated again.
+    pub fn request_chunk(&mut self, chunk_pos: Position) -> io::Result<()> {
+        if self.chunk_state(chunk_pos) != ChunkState::Unloaded {
+            return Ok(());
+        }
+
+        match self.take_unloaded_chunk(chunk_pos)? {
+            Some(unloaded_chunk) => {
+                self.install_chunk(unloaded_chunk);
+            }
+            None => {
+                self.pending_chunks.insert(chunk_pos);
+                self.pool.request(chunk_pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs all chunks the worker pool finished generating, returns how many were installed
+    pub fn install_generated_chunks(&mut self) -> usize {
+        let mut installed = 0;
+        for unloaded_chunk in self.pool.finished() {
+            self.pending_chunks.remove(&unloaded_chunk.pos);
+            if self.is_loaded(&unloaded_chunk.pos) {
+                continue;
+            }
+            self.install_chunk(unloaded_chunk);
+            installed += 1;
+        }
+        installed
+    }
+
+    /// Loads the chunk synchronously, generating it on the calling thread if it is not stored
+    pub fn load_chunk(&mut self, chunk_pos: Position) -> io::Result<&LoadedChunk> {
+        if self.chunk_state(chunk_pos) == ChunkState::Failed {
+            return Err(Level::failed_error(chunk_pos));
+        }
         // Does not check if a chunk is already loaded, would lead to the chunk being generated again and overriding the already loaded chunk
-        let chunk_option = self.unloaded_chunks.remove(&chunk_pos);
-        let unloaded_chunk = chunk_option.unwrap_or(UnloadedChunk::generate(chunk_pos, self.seed));
+        let unloaded_chunk = match self.take_unloaded_chunk(chunk_pos)? {
+            Some(unloaded_chunk) => unloaded_chunk,
+            None => UnloadedChunk::generate(chunk_pos, &*self.generator),
+        };
+        Ok(self.install_chunk(unloaded_chunk))
+    }
+
+    /// The hot or stored copy of a chunk, `None` only if it was never stored.
+    /// Any other read error is returned, generating the chunk instead would overwrite the stored copy.
+    /// Chunks that fail to decode are marked as failed, other errors such as missing permissions are
+    /// retried on the next request.
+    fn take_unloaded_chunk(&mut self, chunk_pos: Position) -> io::Result<Option<UnloadedChunk>> {
+        if let Some(unloaded_chunk) = self.hot_chunks.take(chunk_pos) {
+            return Ok(Some(unloaded_chunk));
+        }
+        let result = self.storage.read_chunk(chunk_pos);
+        if let Err(e) = &result {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ) {
+                self.failed_chunks.insert(chunk_pos);
+            }
+        }
+        result
+    }
+
+    fn failed_error(chunk_pos: Position) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Chunk {:?} failed to load from disk", chunk_pos),
+        )
+    }
+
+    pub fn take_restored_entities(&mut self) -> Vec<(Position, Vec<u8>)> {
+        std::mem::take(&mut self.restored_entities)
+    }
+
+    fn install_chunk(&mut self, mut unloaded_chunk: UnloadedChunk) -> &LoadedChunk {
+        let chunk_pos = unloaded_chunk.pos;
+        self.pending_chunks.remove(&chunk_pos);
+
+        let entities = std::mem::take(&mut unloaded_chunk.entities);
+        // The stored copy has to be rewritten on unload, otherwise the entities would be restored twice
+        let stored_with_entities = !entities.is_empty();
+        if stored_with_entities {
+            self.restored_entities.push((chunk_pos, entities));
+        }
 
         //let neighbours = self.get_chunk_neighbours(chunk_pos);
         let mut loaded_chunk = unloaded_chunk.load();
         //loaded_chunk.populate_neighbours(neighbours);
+        loaded_chunk.dirty |= stored_with_entities;
+
+        if let Some(edits) = self.pending_edits.remove(&chunk_pos) {
+            for (tile_pos, tile) in edits {
+                let previous =
+                    loaded_chunk.set_tile_at(TilePosition::to_tile_in_chunk(tile_pos), tile);
+                self.tile_events.single_write(TileChanged {
+                    tile_pos,
+                    previous,
+                    tile,
+                });
+            }
+        }
+
         self.loaded_chunks.insert(chunk_pos, loaded_chunk);
+        self.autotile_chunk(chunk_pos);
+        self.chunk_events
+            .single_write(ChunkEvent::Loaded(chunk_pos));
         return self.loaded_chunks.get(&chunk_pos).unwrap();
     }
 
+    /// Resolves the connections of the wall at `tile_pos` from its 8 neighbours.
+    /// Walls next to chunks that are not loaded keep their connections until that chunk is installed.
+    fn autotile(&mut self, tile_pos: Position) {
+        match self.get_loaded_tile(tile_pos) {
+            Some(tile) if tile.tile_type() == TileType::Wall => {}
+            _ => return,
+        }
+
+        let mut neighbours = [false; 8];
+        for (i, dir) in DIRECTIONS.iter().enumerate() {
+            match self.get_loaded_tile(tile_pos + *dir) {
+                Some(tile) => neighbours[i] = tile.tile_type() == TileType::Wall,
+                None => return,
+            }
+        }
+
+        let chunk = self
+            .loaded_chunks
+            .get_mut(&TilePosition::to_chunk(tile_pos))
+            .unwrap();
+        let tile_chunk_pos = TilePosition::to_tile_in_chunk(tile_pos);
+        let tile = &mut chunk.tiles[tile_chunk_pos.x as usize][tile_chunk_pos.y as usize];
+        // Connections are derived from the neighbours, so this does not make the chunk dirty
+        *tile = tile.with_wall(neighbours);
+    }
+
+    fn autotile_around(&mut self, tile_pos: Position) {
+        self.autotile(tile_pos);
+        for dir in DIRECTIONS {
+            self.autotile(tile_pos + dir);
+        }
+    }
+
+    /// Resolves every wall of the chunk along with the bordering walls of its loaded neighbours
+    fn autotile_chunk(&mut self, chunk_pos: Position) {
+        let chunk_tile_pos = ChunkPosition::to_tile(chunk_pos);
+        for x in -1..=Chunk::SIZE {
+            for y in -1..=Chunk::SIZE {
+                self.autotile(chunk_tile_pos + pos!(x, y));
+            }
+        }
+    }
+
+    /// Tiles in chunks that are not loaded, including pending ones, are always blocked
+    pub fn is_blocked(&self, tile_pos: Position) -> bool {
+        self.get_loaded_tile(tile_pos)
+            .map(|tile| tile.blocked())
+            .unwrap_or(true)
+    }
+
+    /// Tiles in chunks that are not loaded, including pending ones, always block sight
+    pub fn blocks_sight(&self, tile_pos: Position) -> bool {
+        self.get_loaded_tile(tile_pos)
+            .map(|tile| tile.block_sight())
+            .unwrap_or(true)
+    }
+
+    /// Whether no tile between `from` and `to` blocks sight, the end tiles themselves may.
+    ///
+    /// Always follows the `Line` from the lower of the two ends, so sight works both ways.
+    pub fn line_of_sight(&self, from: Position, to: Position) -> bool {
+        let (from, to) = if (from.x, from.y) <= (to.x, to.y) {
+            (from, to)
+        } else {
+            (to, from)
+        };
+        Line::new(from, to)
+            .skip(1)
+            .take_while(|tile_pos| *tile_pos != to)
+            .all(|tile_pos| !self.blocks_sight(tile_pos))
+    }
+
+    /// The first tile blocking sight within `max` tiles of `from` in direction `dir`, the ray starts
+    /// after `from`
+    pub fn raycast(&self, from: Position, dir: Position, max: i32) -> Option<Position> {
+        let length = dir.x.abs().max(dir.y.abs());
+        if length == 0 {
+            return None;
+        }
+        let to = from + pos!(dir.x * max / length, dir.y * max / length);
+        Line::new(from, to)
+            .skip(1)
+            .find(|tile_pos| self.blocks_sight(*tile_pos))
+    }
+
     pub fn get_loaded_tile(&self, tile_pos: Position) -> Option<&Tile> {
         let loaded_chunk = self.loaded_chunks.get(&TilePosition::to_chunk(tile_pos))?;
         let tile_chunk_pos = TilePosition::to_tile_in_chunk(tile_pos);
@@ -98,12 +503,201 @@ impl Level {
         return self.loaded_chunks.get(&chunk_pos);
     }
 
-    pub fn render(&self, atlas: &mut SpriteAtlas, camera: &Camera) -> GameResult {
+    /// Marks loaded tiles as explored, explored chunks are written when they are unloaded
+    pub fn explore<'a, I: IntoIterator<Item = &'a Position>>(&mut self, tiles: I) {
+        for tile_pos in tiles {
+            if let Some(chunk) = self
+                .loaded_chunks
+                .get_mut(&TilePosition::to_chunk(*tile_pos))
+            {
+                if chunk.fog.explore(TilePosition::to_tile_in_chunk(*tile_pos)) {
+                    chunk.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Keeps how an explored tile looked before it changed out of sight
+    pub fn remember(&mut self, tile_pos: Position, tile: Tile) {
+        if let Some(chunk) = self
+            .loaded_chunks
+            .get_mut(&TilePosition::to_chunk(tile_pos))
+        {
+            if chunk
+                .fog
+                .remember(TilePosition::to_tile_in_chunk(tile_pos), tile)
+            {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    /// Renders loaded chunks in view, see `LoadedChunk::render` for how `visible` is used
+    pub fn render(
+        &self,
+        atlas: &mut SpriteAtlas,
+        camera: &Camera,
+        visible: Option<&HashSet<Position>>,
+        light_map: &LightMap,
+    ) -> GameResult {
         for (chunk_pos, chunk) in &self.loaded_chunks {
             if camera.chunk_in_view(*chunk_pos) {
-                chunk.render(atlas, camera)?;
+                chunk.render(atlas, camera, visible, light_map)?;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use crate::world::tile::TileId;
+
+    /// Level of grass chunks with walls on the given tiles, built without generating anything
+    pub(crate) fn hand_built_level(chunks: &[Position], walls: &[Position]) -> Level {
+        let mut level = Level::default();
+        for chunk_pos in chunks {
+            let mut chunk = LoadedChunk::void();
+            chunk.pos = *chunk_pos;
+            chunk.tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+            level.loaded_chunks.insert(*chunk_pos, chunk);
+        }
+        for tile_pos in walls {
+            let chunk = level
+                .loaded_chunks
+                .get_mut(&TilePosition::to_chunk(*tile_pos))
+                .unwrap();
+            chunk.set_tile_at(
+                TilePosition::to_tile_in_chunk(*tile_pos),
+                Tile::wall([false; 8]),
+            );
+        }
+        level
+    }
+
+    #[test]
+    fn line_of_sight_is_symmetric() {
+        let chunks = [pos!(-1, 0), pos!(0, 0)];
+        let walls = (-Chunk::SIZE..Chunk::SIZE)
+            .flat_map(|x| (0..Chunk::SIZE).map(move |y| pos!(x, y)))
+            .filter(|tile_pos| (tile_pos.x * 7 + tile_pos.y * 13).rem_euclid(11) == 0)
+            .collect::<Vec<_>>();
+        let level = hand_built_level(&chunks, &walls);
+
+        let points = (-12..12)
+            .step_by(3)
+            .flat_map(|x| (1..20).step_by(4).map(move |y| pos!(x, y)))
+            .collect::<Vec<_>>();
+        let mut seen = 0;
+        for a in &points {
+            for b in &points {
+                assert_eq!(
+                    level.line_of_sight(*a, *b),
+                    level.line_of_sight(*b, *a),
+                    "{:?} and {:?}",
+                    a,
+                    b
+                );
+                seen += level.line_of_sight(*a, *b) as i32;
+            }
+        }
+        // Some pairs see each other, some do not
+        assert!(seen > points.len() as i32 && seen < (points.len() * points.len()) as i32);
+    }
+
+    #[test]
+    fn raycasts_stop_at_the_first_blocking_tile_across_chunks() {
+        let chunks = [pos!(0, 0), pos!(1, 0)];
+        let walls = [pos!(Chunk::SIZE + 3, 5), pos!(Chunk::SIZE + 6, 5)];
+        let level = hand_built_level(&chunks, &walls);
+
+        assert_eq!(
+            level.raycast(pos!(20, 5), pos!(1, 0), 20),
+            Some(pos!(Chunk::SIZE + 3, 5))
+        );
+        assert_eq!(level.raycast(pos!(20, 5), pos!(1, 0), 10), None);
+        assert_eq!(level.raycast(pos!(20, 5), pos!(-1, 0), 10), None);
+        assert!(!level.line_of_sight(pos!(20, 5), pos!(Chunk::SIZE + 4, 5)));
+        assert!(level.line_of_sight(pos!(20, 5), pos!(Chunk::SIZE + 3, 5)));
+    }
+
+    #[test]
+    fn unloaded_chunks_block_rays() {
+        let chunks = [pos!(0, 0), pos!(1, 0)];
+        let level = hand_built_level(&chunks, &[]);
+
+        // Chunk (2, 0) is not loaded, the ray stops at its first tile
+        let edge = Chunk::SIZE * 2;
+        assert_eq!(
+            level.raycast(pos!(edge - 4, 9), pos!(1, 0), 10),
+            Some(pos!(edge, 9))
+        );
+        assert_eq!(
+            level.raycast(pos!(3, 2), pos!(0, -1), 10),
+            Some(pos!(3, -1))
+        );
+        assert!(!level.line_of_sight(pos!(edge - 4, 9), pos!(edge + 2, 9)));
+        assert!(level.line_of_sight(pos!(edge - 4, 9), pos!(edge - 1, 9)));
+    }
+
+    #[test]
+    fn undecodable_chunks_are_never_replaced() {
+        let dir = env::temp_dir().join(format!("rusted-level-failed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut level = Level::with_dir(3, GeneratorKind::Perlin, &dir);
+
+        let chunk_pos = pos!(-2, 1);
+        let mut chunk = UnloadedChunk::generate(chunk_pos, &*level.generator);
+        let mut tiles = chunk.tiles.decompress();
+        tiles[4][7] = Tile::new(TileId(u16::MAX));
+        chunk.tiles = CompressedTiles::compress(&tiles);
+        level.storage.write_all([&chunk]).unwrap();
+
+        let error = level.request_chunk(chunk_pos).unwrap_err();
+        assert!(error.to_string().contains("Unknown tile id"), "{}", error);
+        assert_eq!(level.chunk_state(chunk_pos), ChunkState::Failed);
+        assert!(level.is_blocked(ChunkPosition::to_tile(chunk_pos)));
+
+        // Not retried or generated, edits are refused instead of creating a copy that would be written
+        level.request_chunk(chunk_pos).unwrap();
+        assert_eq!(level.chunk_state(chunk_pos), ChunkState::Failed);
+        assert!(level.load_chunk(chunk_pos).is_err());
+        assert!(level
+            .set_tile(ChunkPosition::to_tile(chunk_pos), Tile::grass())
+            .is_err());
+        assert!(!level.hot_chunks.contains(chunk_pos));
+
+        level.save_chunks().unwrap();
+        assert!(level.storage.read_chunk(chunk_pos).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edits_to_chunks_never_stored_wait_for_the_pool() {
+        let dir = env::temp_dir().join(format!("rusted-level-edits-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut level = Level::with_dir(5, GeneratorKind::Perlin, &dir);
+        let mut reader = level.tile_events.register_reader();
+
+        let tile_pos = ChunkPosition::to_tile(pos!(4, -3)) + pos!(2, 9);
+        level.set_tile(tile_pos, Tile::sand()).unwrap();
+        assert_eq!(level.chunk_state(pos!(4, -3)), ChunkState::Pending);
+        assert_eq!(level.tile_events.read(&mut reader).count(), 0);
+
+        while level.chunk_state(pos!(4, -3)) == ChunkState::Pending {
+            level.install_generated_chunks();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(level.get_loaded_tile(tile_pos), Some(&Tile::sand()));
+        let changes = level.tile_events.read(&mut reader).collect::<Vec<_>>();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].tile_pos, tile_pos);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,142 @@
+use ggez::graphics::Color;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::world::fov::Fov;
+use crate::world::tile_registry::TileRegistry;
+use crate::{Position, Tile};
+
+/// Light given off by an entity or a glowing tile
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub radius: i32,
+    pub color: [f32; 3],
+    /// Exponent of the falloff towards the edge of the radius, 1 is linear
+    #[serde(default = "Light::default_falloff")]
+    pub falloff: f32,
+}
+
+impl Light {
+    fn default_falloff() -> f32 {
+        1.0
+    }
+
+    /// Share of the light's colour reaching a tile `dx`, `dy` tiles away
+    pub fn intensity_at(&self, dx: i32, dy: i32) -> f32 {
+        let distance = ((dx * dx + dy * dy) as f32).sqrt();
+        (1.0 - distance / (self.radius + 1) as f32)
+            .max(0.0)
+            .powf(self.falloff)
+    }
+}
+
+/// Light of every tile reached by a light source, summed up every tick by the `LightingSystem`.
+///
+/// Tiles get ambient light on top of that, daylight above ground and next to none underground.
+pub struct LightMap {
+    lit: HashMap<Position, [f32; 3]>,
+    pub daylight: [f32; 3],
+    pub underground: [f32; 3],
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        LightMap {
+            lit: HashMap::new(),
+            daylight: LightMap::DAYLIGHT,
+            underground: LightMap::UNDERGROUND,
+        }
+    }
+}
+
+impl LightMap {
+    pub const DAYLIGHT: [f32; 3] = [0.85, 0.85, 0.85];
+    pub const UNDERGROUND: [f32; 3] = [0.04, 0.04, 0.07];
+    /// Floors with this tag are underground, such as cave and dungeon floors
+    pub const UNDERGROUND_TAG: &'static str = "underground";
+
+    pub fn clear(&mut self) {
+        self.lit.clear();
+    }
+
+    /// Light added to every tile the light reaches from `origin`, stopping at tiles that block sight
+    pub fn lit_tiles<F: Fn(Position) -> bool>(
+        origin: Position,
+        light: &Light,
+        is_opaque: F,
+    ) -> Vec<(Position, [f32; 3])> {
+        let mut lit_tiles = Vec::new();
+        for tile_pos in Fov::compute(origin, light.radius, is_opaque) {
+            let intensity = light.intensity_at(tile_pos.x - origin.x, tile_pos.y - origin.y);
+            if intensity <= 0.0 {
+                continue;
+            }
+            lit_tiles.push((tile_pos, light.color.map(|color| color * intensity)));
+        }
+        lit_tiles
+    }
+
+    /// Adds light calculated by `lit_tiles` to the map
+    pub fn add(&mut self, lit_tiles: &[(Position, [f32; 3])]) {
+        for (tile_pos, light) in lit_tiles {
+            let lit = self.lit.entry(*tile_pos).or_insert([0.0; 3]);
+            for (channel, light) in lit.iter_mut().zip(light) {
+                *channel += light;
+            }
+        }
+    }
+
+    /// Ambient and source light at a tile, each channel clamped to 1
+    pub fn light_at(&self, tile_pos: Position, tile: &Tile) -> [f32; 3] {
+        let registry = TileRegistry::global();
+        let mut light = if registry.def(tile.floor).has_tag(LightMap::UNDERGROUND_TAG) {
+            self.underground
+        } else {
+            self.daylight
+        };
+        if let Some(lit) = self.lit.get(&tile_pos) {
+            for (channel, lit) in light.iter_mut().zip(lit) {
+                *channel = (*channel + lit).min(1.0);
+            }
+        }
+        light
+    }
+
+    /// Strongest channel of the light at a tile, from 0 in darkness to 1
+    pub fn brightness_at(&self, tile_pos: Position, tile: &Tile) -> f32 {
+        self.light_at(tile_pos, tile)
+            .iter()
+            .fold(0.0, |max, channel| channel.max(max))
+    }
+
+    /// Tint of a sprite drawn at the tile
+    pub fn color_at(&self, tile_pos: Position, tile: &Tile) -> Color {
+        let [r, g, b] = self.light_at(tile_pos, tile);
+        Color::new(r, g, b, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LightMap;
+    use crate::world::tile::Tile;
+    use crate::{pos, Position};
+
+    #[test]
+    fn only_cave_floors_are_underground() {
+        let light_map = LightMap::default();
+        let tile_pos = pos!(0, 0);
+        assert_eq!(
+            light_map.light_at(tile_pos, &Tile::empty()),
+            light_map.daylight
+        );
+        assert_eq!(
+            light_map.light_at(tile_pos, &Tile::grass()),
+            light_map.daylight
+        );
+        assert_eq!(
+            light_map.light_at(tile_pos, &Tile::cave_floor()),
+            light_map.underground
+        );
+    }
+}
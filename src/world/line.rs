@@ -0,0 +1,97 @@
+use crate::{pos, Position};
+
+/// Tile positions on a straight line from `from` to `to`, both included, using Bresenham's algorithm.
+///
+/// Every step moves one tile along the major axis, so the line has `from.dist(to) + 1` positions.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pos: Position,
+    end: Position,
+    delta: Position,
+    step: Position,
+    error: i32,
+    done: bool,
+}
+
+impl Line {
+    pub fn new(from: Position, to: Position) -> Self {
+        let delta = pos!((to.x - from.x).abs(), -(to.y - from.y).abs());
+        Line {
+            pos: from,
+            end: to,
+            delta,
+            step: pos!((to.x - from.x).signum(), (to.y - from.y).signum()),
+            error: delta.x + delta.y,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Line {
+    type Item = Position;
+
+    fn next(&mut self) -> Option<Position> {
+        if self.done {
+            return None;
+        }
+        let current = self.pos;
+        if current == self.end {
+            self.done = true;
+            return Some(current);
+        }
+
+        let doubled = 2 * self.error;
+        if doubled >= self.delta.y {
+            self.error += self.delta.y;
+            self.pos.x += self.step.x;
+        }
+        if doubled <= self.delta.x {
+            self.error += self.delta.x;
+            self.pos.y += self.step.y;
+        }
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Line;
+    use crate::{pos, Position};
+
+    #[test]
+    fn lines_include_both_ends_in_every_octant() {
+        let from = pos!(-3, 4);
+        let offsets = [
+            pos!(7, 2),
+            pos!(2, 7),
+            pos!(-2, 7),
+            pos!(-7, 2),
+            pos!(-7, -2),
+            pos!(-2, -7),
+            pos!(2, -7),
+            pos!(7, -2),
+            pos!(5, 0),
+            pos!(0, -5),
+            pos!(4, 4),
+            pos!(-4, 4),
+            pos!(0, 0),
+        ];
+        for offset in offsets {
+            let to = from + offset;
+            let line = Line::new(from, to).collect::<Vec<_>>();
+            assert_eq!(line.first(), Some(&from), "{:?}", offset);
+            assert_eq!(line.last(), Some(&to), "{:?}", offset);
+            assert_eq!(line.len() as i32, from.dist(to) + 1, "{:?}", offset);
+            for pair in line.windows(2) {
+                let step = pair[1] - pair[0];
+                assert_eq!(pair[0].dist(pair[1]), 1, "{:?}", offset);
+                // Never steps against the direction of the line
+                assert!(
+                    step.x * offset.x >= 0 && step.y * offset.y >= 0,
+                    "{:?}",
+                    offset
+                );
+            }
+        }
+    }
+}
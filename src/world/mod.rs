@@ -1,5 +1,19 @@
 pub mod chunk;
+pub mod chunk_pool;
+pub mod compressed_tiles;
+pub mod dijkstra_map;
 pub mod direction;
+pub mod fog;
+pub mod fov;
+pub mod generation;
+pub mod hot_chunks;
 pub mod level;
+pub mod light;
+pub mod line;
+pub mod occupancy;
+pub mod portal_graph;
 pub mod position;
+pub mod region;
+pub mod spatial_index;
 pub mod tile;
+pub mod tile_registry;
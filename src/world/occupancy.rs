@@ -0,0 +1,58 @@
+use hashbrown::HashMap;
+use specs::Entity;
+
+use crate::Position;
+
+/// Tiles taken by blocking entities, kept in sync by the `MovementSystem`
+#[derive(Debug, Default)]
+pub struct Occupancy {
+    occupants: HashMap<Position, Entity>,
+}
+
+impl Occupancy {
+    /// Extra cost pathfinding gives occupied tiles, high enough to walk around but still path through crowds
+    pub const COST: i32 = 20;
+
+    pub fn clear(&mut self) {
+        self.occupants.clear();
+    }
+
+    pub fn occupant(&self, tile_pos: Position) -> Option<Entity> {
+        self.occupants.get(&tile_pos).copied()
+    }
+
+    pub fn is_occupied(&self, tile_pos: Position) -> bool {
+        self.occupants.contains_key(&tile_pos)
+    }
+
+    /// Takes a tile for the entity, returns false if another entity already occupies it
+    pub fn occupy(&mut self, tile_pos: Position, entity: Entity) -> bool {
+        match self.occupants.get(&tile_pos) {
+            Some(occupant) if *occupant != entity => false,
+            _ => {
+                self.occupants.insert(tile_pos, entity);
+                true
+            }
+        }
+    }
+
+    /// Moves the entity between tiles, returns false and leaves it in place if `to` is occupied
+    pub fn move_entity(&mut self, entity: Entity, from: Position, to: Position) -> bool {
+        if !self.occupy(to, entity) {
+            return false;
+        }
+        if from != to && self.occupant(from) == Some(entity) {
+            self.occupants.remove(&from);
+        }
+        true
+    }
+
+    /// Extra pathfinding cost of stepping onto a tile
+    pub fn cost_at(&self, tile_pos: Position) -> i32 {
+        if self.is_occupied(tile_pos) {
+            Occupancy::COST
+        } else {
+            0
+        }
+    }
+}
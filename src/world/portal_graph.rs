@@ -0,0 +1,399 @@
+use hashbrown::HashMap;
+use pathfinding::prelude::{astar, dijkstra_all};
+
+use crate::world::chunk::Chunk;
+use crate::world::direction::DIRECTIONS;
+use crate::world::occupancy::Occupancy;
+use crate::world::position::{ChunkPosition, TilePosition};
+use crate::{pos, Level, Position};
+
+/// Entrances between chunks and the cheapest paths between them, for hierarchical pathfinding (HPA*).
+///
+/// Every run of passable tiles along the border of two loaded chunks gets an entrance, a pair of
+/// tiles facing each other across the border. Paths are first searched over entrances only, then
+/// refined into tiles one chunk at a time, so long paths only ever search within single chunks.
+#[derive(Debug, Default)]
+pub struct PortalGraph {
+    /// Edges from each entrance tile, to the other entrances of its chunk and across the border
+    chunks: HashMap<Position, HashMap<Position, Vec<(Position, i32)>>>,
+}
+
+impl PortalGraph {
+    /// Runs of passable border tiles wider than this get an entrance at both ends instead of the middle
+    pub const MAX_ENTRANCE_WIDTH: i32 = 6;
+    /// Border sides of a chunk, the tile offset across the border
+    pub const SIDES: [Position; 4] = [pos!(1, 0), pos!(-1, 0), pos!(0, 1), pos!(0, -1)];
+
+    pub fn contains(&self, chunk_pos: Position) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    /// Drops chunks that are no longer loaded, such as after the level was replaced
+    pub fn retain_loaded(&mut self, level: &Level) {
+        self.chunks
+            .retain(|chunk_pos, _| level.is_loaded(chunk_pos));
+    }
+
+    /// Finds the entrances of a loaded chunk and the paths between them, replacing previous ones.
+    /// Entrances towards a neighbour are only found while that neighbour is loaded.
+    pub fn build(&mut self, chunk_pos: Position, level: &Level) {
+        if !level.is_loaded(&chunk_pos) {
+            self.chunks.remove(&chunk_pos);
+            return;
+        }
+
+        let mut edges = HashMap::<Position, Vec<(Position, i32)>>::new();
+        for side in PortalGraph::SIDES {
+            for (tile_pos, across) in PortalGraph::entrances(chunk_pos, side, level) {
+                if let Some(cost) = PortalGraph::step_cost(level, across) {
+                    edges.entry(tile_pos).or_default().push((across, cost));
+                }
+            }
+        }
+
+        let entrances = edges.keys().copied().collect::<Vec<_>>();
+        for entrance in &entrances {
+            let costs = PortalGraph::chunk_costs(*entrance, false, level, None);
+            let paths = edges.get_mut(entrance).unwrap();
+            for other in entrances.iter().filter(|other| *other != entrance) {
+                if let Some(cost) = costs.get(other) {
+                    paths.push((*other, *cost));
+                }
+            }
+        }
+
+        self.chunks.insert(chunk_pos, edges);
+    }
+
+    /// Entrance tiles on one side of a chunk paired with the tile across the border.
+    ///
+    /// Both chunks of a border scan it in the same order, so they agree on where its entrances are.
+    fn entrances(chunk_pos: Position, side: Position, level: &Level) -> Vec<(Position, Position)> {
+        let chunk_tile_pos = ChunkPosition::to_tile(chunk_pos);
+        let last = Chunk::SIZE - 1;
+        let (first, along) = match (side.x, side.y) {
+            (1, _) => (pos!(last, 0), pos!(0, 1)),
+            (-1, _) => (pos!(0, 0), pos!(0, 1)),
+            (_, 1) => (pos!(0, last), pos!(1, 0)),
+            _ => (pos!(0, 0), pos!(1, 0)),
+        };
+
+        let open = |i: i32| {
+            let tile_pos = chunk_tile_pos + first + along * i;
+            !level.is_blocked(tile_pos) && !level.is_blocked(tile_pos + side)
+        };
+
+        let mut entrances = Vec::new();
+        let mut i = 0;
+        while i < Chunk::SIZE {
+            if !open(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i + 1 < Chunk::SIZE && open(i + 1) {
+                i += 1;
+            }
+            let end = i;
+            let picks = if end - start + 1 > PortalGraph::MAX_ENTRANCE_WIDTH {
+                vec![start, end]
+            } else {
+                vec![(start + end) / 2]
+            };
+            for pick in picks {
+                let tile_pos = chunk_tile_pos + first + along * pick;
+                entrances.push((tile_pos, tile_pos + side));
+            }
+            i += 1;
+        }
+        entrances
+    }
+
+    /// Cost of stepping onto a tile, `None` for blocked tiles and tiles of chunks that are not loaded
+    pub fn step_cost(level: &Level, tile_pos: Position) -> Option<i32> {
+        level
+            .get_loaded_tile(tile_pos)
+            .filter(|tile| !tile.blocked())
+            .map(|tile| tile.traversal_cost())
+    }
+
+    /// Cost of stepping onto a tile during a search, occupied tiles cost extra when given an `Occupancy`
+    fn search_cost(
+        level: &Level,
+        occupancy: Option<&Occupancy>,
+        tile_pos: Position,
+    ) -> Option<i32> {
+        let cost = PortalGraph::step_cost(level, tile_pos)?;
+        Some(cost + occupancy.map_or(0, |occupancy| occupancy.cost_at(tile_pos)))
+    }
+
+    fn chunk_neighbours(
+        tile_pos: Position,
+        level: &Level,
+        occupancy: Option<&Occupancy>,
+    ) -> Vec<(Position, i32)> {
+        let chunk_pos = TilePosition::to_chunk(tile_pos);
+        DIRECTIONS
+            .iter()
+            .map(|dir| tile_pos + *dir)
+            .filter(|n_pos| TilePosition::to_chunk(*n_pos) == chunk_pos)
+            .filter_map(|n_pos| {
+                PortalGraph::search_cost(level, occupancy, n_pos).map(|cost| (n_pos, cost))
+            })
+            .collect()
+    }
+
+    /// Cheapest costs from `from` to every tile of its chunk reachable without leaving it,
+    /// or with `reverse` from every such tile to `from`
+    fn chunk_costs(
+        from: Position,
+        reverse: bool,
+        level: &Level,
+        occupancy: Option<&Occupancy>,
+    ) -> HashMap<Position, i32> {
+        let reached = if reverse {
+            // Stepping back from a tile costs what stepping onto it did
+            dijkstra_all(&from, |tile_pos| {
+                let cost = PortalGraph::search_cost(level, occupancy, *tile_pos).unwrap_or(1);
+                PortalGraph::chunk_neighbours(*tile_pos, level, occupancy)
+                    .into_iter()
+                    .map(move |(n_pos, _)| (n_pos, cost))
+            })
+        } else {
+            dijkstra_all(&from, |tile_pos| {
+                PortalGraph::chunk_neighbours(*tile_pos, level, occupancy)
+            })
+        };
+        let mut costs = reached
+            .into_iter()
+            .map(|(tile_pos, (_, cost))| (tile_pos, cost))
+            .collect::<HashMap<_, _>>();
+        costs.insert(from, 0);
+        costs
+    }
+
+    /// Cheapest path from `from` to `to` including both, along with its cost.
+    ///
+    /// Both tiles have to be in chunks of the graph, paths only cross chunks through their entrances.
+    /// Tiles occupied by entities cost extra within the chunks of `from` and `to` and while refining,
+    /// the paths between entrances are kept as they were built.
+    pub fn find_path(
+        &self,
+        from: Position,
+        to: Position,
+        level: &Level,
+        occupancy: &Occupancy,
+    ) -> Option<(Vec<Position>, i32)> {
+        let from_chunk = TilePosition::to_chunk(from);
+        let to_chunk = TilePosition::to_chunk(to);
+        if !self.contains(from_chunk) || !self.contains(to_chunk) || level.is_blocked(to) {
+            return None;
+        }
+
+        let from_costs = PortalGraph::chunk_costs(from, false, level, Some(occupancy));
+        let to_costs = PortalGraph::chunk_costs(to, true, level, Some(occupancy));
+
+        let mut start_edges = Vec::new();
+        for entrance in self.chunks[&from_chunk].keys() {
+            if let Some(cost) = from_costs.get(entrance) {
+                start_edges.push((*entrance, *cost));
+            }
+        }
+        if let Some(cost) = from_costs.get(&to) {
+            start_edges.push((to, *cost));
+        }
+        let mut goal_edges = HashMap::new();
+        for entrance in self.chunks[&to_chunk].keys() {
+            if let Some(cost) = to_costs.get(entrance) {
+                goal_edges.insert(*entrance, *cost);
+            }
+        }
+
+        let successors = |node: &Position| {
+            let mut edges = Vec::new();
+            if *node == from {
+                edges.extend(start_edges.iter().copied());
+            }
+            if let Some(node_edges) = self
+                .chunks
+                .get(&TilePosition::to_chunk(*node))
+                .and_then(|chunk| chunk.get(node))
+            {
+                edges.extend(node_edges.iter().copied());
+            }
+            if let Some(cost) = goal_edges.get(node) {
+                edges.push((to, *cost));
+            }
+            edges
+        };
+        let (nodes, cost) = astar(&from, successors, |node| node.dist(to), |node| *node == to)?;
+
+        // Consecutive nodes are either across a border or within the same chunk
+        let mut path = vec![from];
+        for pair in nodes.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if TilePosition::to_chunk(a) != TilePosition::to_chunk(b) {
+                path.push(b);
+                continue;
+            }
+            let (segment, _) = astar(
+                &a,
+                |tile_pos| PortalGraph::chunk_neighbours(*tile_pos, level, Some(occupancy)),
+                |tile_pos| tile_pos.dist(b),
+                |tile_pos| *tile_pos == b,
+            )?;
+            path.extend(segment.into_iter().skip(1));
+        }
+        Some((path, cost))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PortalGraph;
+    use crate::world::chunk::{Chunk, LoadedChunk};
+    use crate::world::occupancy::Occupancy;
+    use crate::world::position::{ChunkPosition, TilePosition};
+    use crate::{pos, Level, Position, Tile};
+
+    /// Level of open chunks with walls on the given tiles, built without generating anything
+    fn hand_built_level(chunks: &[Position], walls: &[Position]) -> Level {
+        let mut level = Level::default();
+        for chunk_pos in chunks {
+            let mut chunk = LoadedChunk::void();
+            chunk.pos = *chunk_pos;
+            chunk.tiles = [[Tile::grass(); Chunk::SIZE as usize]; Chunk::SIZE as usize];
+            level.loaded_chunks.insert(*chunk_pos, chunk);
+        }
+        for tile_pos in walls {
+            let chunk = level
+                .loaded_chunks
+                .get_mut(&TilePosition::to_chunk(*tile_pos))
+                .unwrap();
+            chunk.set_tile_at(
+                TilePosition::to_tile_in_chunk(*tile_pos),
+                Tile::wall([false; 8]),
+            );
+        }
+        level
+    }
+
+    fn build_graph(chunks: &[Position], level: &Level) -> PortalGraph {
+        let mut graph = PortalGraph::default();
+        for chunk_pos in chunks {
+            graph.build(*chunk_pos, level);
+        }
+        graph
+    }
+
+    /// Checks the path goes from `from` to `to` in single steps over passable tiles, returns its chunks
+    fn assert_walkable(
+        path: &[Position],
+        from: Position,
+        to: Position,
+        level: &Level,
+    ) -> Vec<Position> {
+        assert_eq!(path.first(), Some(&from));
+        assert_eq!(path.last(), Some(&to));
+        let mut chunks = Vec::new();
+        for pair in path.windows(2) {
+            assert_eq!(pair[0].dist(pair[1]), 1, "{:?} to {:?}", pair[0], pair[1]);
+            assert!(!level.is_blocked(pair[1]), "{:?} is blocked", pair[1]);
+        }
+        for tile_pos in path {
+            let chunk_pos = TilePosition::to_chunk(*tile_pos);
+            if chunks.last() != Some(&chunk_pos) {
+                chunks.push(chunk_pos);
+            }
+        }
+        chunks
+    }
+
+    #[test]
+    fn paths_cross_two_chunk_borders() {
+        let chunks = [pos!(0, 0), pos!(1, 0), pos!(2, 0)];
+        // A wall across the middle chunk with a single gap
+        let walls = (0..Chunk::SIZE)
+            .filter(|y| *y != 27)
+            .map(|y| pos!(Chunk::SIZE + 16, y))
+            .collect::<Vec<_>>();
+        let level = hand_built_level(&chunks, &walls);
+        let graph = build_graph(&chunks, &level);
+
+        let from = pos!(2, 5);
+        let to = pos!(Chunk::SIZE * 2 + 29, 3);
+        let (path, _) = graph
+            .find_path(from, to, &level, &Occupancy::default())
+            .unwrap();
+        let visited = assert_walkable(&path, from, to, &level);
+        assert_eq!(visited, chunks);
+        assert!(path.contains(&pos!(Chunk::SIZE + 16, 27)));
+    }
+
+    #[test]
+    fn paths_leave_and_reenter_the_start_chunk() {
+        let chunks = [pos!(0, 0), pos!(0, 1)];
+        // The start chunk is cut in half, the only way around is through the chunk below
+        let walls = (0..Chunk::SIZE).map(|y| pos!(16, y)).collect::<Vec<_>>();
+        let level = hand_built_level(&chunks, &walls);
+        let graph = build_graph(&chunks, &level);
+
+        let from = pos!(4, 4);
+        let to = pos!(28, 4);
+        let (path, _) = graph
+            .find_path(from, to, &level, &Occupancy::default())
+            .unwrap();
+        let visited = assert_walkable(&path, from, to, &level);
+        assert_eq!(visited, vec![pos!(0, 0), pos!(0, 1), pos!(0, 0)]);
+
+        // Without the chunk below there is no way around
+        let mut graph = graph;
+        let mut level = level;
+        level.loaded_chunks.remove(&pos!(0, 1));
+        graph.retain_loaded(&level);
+        graph.build(pos!(0, 0), &level);
+        assert!(graph
+            .find_path(from, to, &level, &Occupancy::default())
+            .is_none());
+    }
+
+    #[test]
+    fn both_sides_of_a_border_agree_on_entrances() {
+        let chunks = [pos!(-1, 0), pos!(0, 0), pos!(0, -1)];
+        let chunk_tile_pos = ChunkPosition::to_tile(pos!(0, 0));
+        // Runs of every width along the border, walls on either side of it
+        let mut walls = Vec::new();
+        for y in [0, 3, 5, 6, 14, 15, 31] {
+            walls.push(chunk_tile_pos + pos!(0, y));
+        }
+        for y in [1, 7, 22] {
+            walls.push(chunk_tile_pos + pos!(-1, y));
+        }
+        for x in [2, 9, 10, 11] {
+            walls.push(chunk_tile_pos + pos!(x, 0));
+        }
+        let level = hand_built_level(&chunks, &walls);
+        let graph = build_graph(&chunks, &level);
+
+        let mut crossings = 0;
+        for chunk_pos in chunks {
+            for (entrance, edges) in &graph.chunks[&chunk_pos] {
+                for (across, cost) in edges {
+                    let across_chunk = TilePosition::to_chunk(*across);
+                    if across_chunk == chunk_pos {
+                        continue;
+                    }
+                    crossings += 1;
+                    assert_eq!(*cost, PortalGraph::step_cost(&level, *across).unwrap());
+                    let back = graph.chunks[&across_chunk]
+                        .get(across)
+                        .unwrap_or_else(|| panic!("{:?} has no entrance back", across));
+                    assert!(back.iter().any(|(tile_pos, _)| tile_pos == entrance));
+                }
+            }
+        }
+        // Runs along the side: 2, 4, 8-13, 16-21 and 23-30 at both ends. Along the top: 1, 3-8 and
+        // 12-31 at both ends. Each entrance is crossed from both sides
+        assert_eq!(crossings, 2 * 10);
+    }
+}
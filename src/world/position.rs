@@ -1,9 +1,11 @@
 use crate::world::chunk::Chunk;
 use crate::world::direction::Direction;
+use crate::world::region::Region;
 use crate::world::tile::Tile;
 use crate::Camera;
 use gfx_core::SubmissionResult;
 use imgui::sys::__BindgenBitfieldUnit;
+use serde::{Deserialize, Serialize};
 use specs::{Component, VecStorage};
 use std::cmp::min;
 use std::collections::Bound;
@@ -82,6 +84,20 @@ impl ChunkPosition {
     pub fn to_screen(chunk_pos: Position, camera: &Camera) -> Position {
         return WorldPosition::to_screen(ChunkPosition::to_world(chunk_pos), camera);
     }
+
+    pub fn to_region(chunk_pos: Position) -> Position {
+        Position {
+            x: chunk_pos.x.div_euclid(Region::SIZE),
+            y: chunk_pos.y.div_euclid(Region::SIZE),
+        }
+    }
+
+    pub fn to_chunk_in_region(chunk_pos: Position) -> Position {
+        Position {
+            x: chunk_pos.x.rem_euclid(Region::SIZE),
+            y: chunk_pos.y.rem_euclid(Region::SIZE),
+        }
+    }
 }
 
 pub struct WorldPosition;
@@ -124,7 +140,7 @@ impl WorldPosition {
 
 pub struct ScreenPosition;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
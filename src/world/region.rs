@@ -0,0 +1,273 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+
+use crate::world::chunk::UnloadedChunk;
+use crate::world::position::ChunkPosition;
+use crate::world::tile_registry::TileRegistry;
+use crate::Position;
+
+/// A region groups `Region::SIZE` x `Region::SIZE` chunks into a single file on disk.
+///
+/// File layout:
+/// - magic bytes `RGN1`
+/// - index header of `Region::CHUNKS` entries, each a little endian `u32` offset and `u32` length
+///   (an entry with length 0 means the chunk has never been stored)
+/// - chunk data, bincode encoded `UnloadedChunk`s at the offsets given by the index
+pub struct Region;
+
+impl Region {
+    pub const SIZE: i32 = 8;
+    pub const CHUNKS: usize = (Region::SIZE * Region::SIZE) as usize;
+
+    const MAGIC: &'static [u8; 4] = b"RGN1";
+    const HEADER_SIZE: u64 = 4 + Region::CHUNKS as u64 * 8;
+
+    fn index(chunk_pos: Position) -> usize {
+        let in_region = ChunkPosition::to_chunk_in_region(chunk_pos);
+        (in_region.y * Region::SIZE + in_region.x) as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegionStorage {
+    dir: PathBuf,
+}
+
+impl RegionStorage {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        RegionStorage { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn region_path(&self, region_pos: Position) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.region", region_pos.x, region_pos.y))
+    }
+
+    pub fn read_chunk(&self, chunk_pos: Position) -> io::Result<Option<UnloadedChunk>> {
+        let path = self.region_path(ChunkPosition::to_region(chunk_pos));
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let index = Self::read_index(&mut file)?;
+        let (offset, length) = index[Region::index(chunk_pos)];
+        if length == 0 {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut data)?;
+
+        let chunk: UnloadedChunk =
+            bincode::deserialize(&data).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        if chunk.pos != chunk_pos {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Region file {:?} has chunk {:?} stored at the index of {:?}",
+                    path, chunk.pos, chunk_pos
+                ),
+            ));
+        }
+        if let Err(e) = chunk.tiles.validate() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Chunk {:?} in region file {:?}: {}", chunk_pos, path, e),
+            ));
+        }
+        if let Err(e) = TileRegistry::global()
+            .validate(chunk.tiles.palette.iter().chain(chunk.fog.remembered()))
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Chunk {:?} in region file {:?}: {}", chunk_pos, path, e),
+            ));
+        }
+
+        Ok(Some(chunk))
+    }
+
+    /// Writes several encoded chunks belonging to the same region, rewriting the region file once.
+    /// Entries of other chunks are copied over as they are, even ones that no longer decode.
+    fn write_chunks(
+        &self,
+        region_pos: Position,
+        chunks: Vec<(Position, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let path = self.region_path(region_pos);
+        let mut entries = self.read_entries(&path)?;
+        for (chunk_pos, data) in chunks {
+            entries[Region::index(chunk_pos)] = Some(data);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first so a crash mid-write never corrupts the existing region
+        let tmp_path = path.with_extension("region.tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(Region::MAGIC)?;
+
+            let mut offset = Region::HEADER_SIZE as u32;
+            for entry in &entries {
+                let length = entry.as_ref().map(|data| data.len() as u32).unwrap_or(0);
+                let entry_offset = if length == 0 { 0 } else { offset };
+                file.write_all(&entry_offset.to_le_bytes())?;
+                file.write_all(&length.to_le_bytes())?;
+                offset += length;
+            }
+
+            for data in entries.iter().flatten() {
+                file.write_all(data)?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    /// Writes all given chunks, grouping them by region so every region file is rewritten and synced once.
+    pub fn write_all<'a, I: IntoIterator<Item = &'a UnloadedChunk>>(
+        &self,
+        chunks: I,
+    ) -> io::Result<()> {
+        let mut regions = HashMap::<Position, Vec<(Position, Vec<u8>)>>::new();
+        for chunk in chunks {
+            let data =
+                bincode::serialize(chunk).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            regions
+                .entry(ChunkPosition::to_region(chunk.pos))
+                .or_default()
+                .push((chunk.pos, data));
+        }
+
+        for (region_pos, chunks) in regions {
+            self.write_chunks(region_pos, chunks)?;
+        }
+        Ok(())
+    }
+
+    fn read_index(file: &mut File) -> io::Result<Vec<(u32, u32)>> {
+        let mut header = vec![0u8; Region::HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+
+        if &header[0..4] != Region::MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Region file has an invalid header",
+            ));
+        }
+
+        let index = header[4..]
+            .chunks_exact(8)
+            .map(|entry| {
+                let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                let length = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+                (offset, length)
+            })
+            .collect();
+        Ok(index)
+    }
+
+    fn read_entries(&self, path: &Path) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let mut entries = vec![None; Region::CHUNKS];
+
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e),
+        };
+
+        let index = Self::read_index(&mut file)?;
+        for (i, (offset, length)) in index.into_iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let mut data = vec![0u8; length as usize];
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.read_exact(&mut data)?;
+            entries[i] = Some(data);
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::pos;
+    use crate::world::generation::GeneratorKind;
+    use crate::world::tile::Tile;
+
+    fn temp_storage(name: &str) -> RegionStorage {
+        let dir = env::temp_dir().join(format!("rusted-region-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        RegionStorage::new(dir)
+    }
+
+    fn assert_same(read: &UnloadedChunk, written: &UnloadedChunk) {
+        assert_eq!(read.pos, written.pos);
+        assert_eq!(read.tiles.decompress(), written.tiles.decompress());
+        assert_eq!(read.entities, written.entities);
+        assert_eq!(read.fog, written.fog);
+    }
+
+    #[test]
+    fn chunks_roundtrip_within_regions() {
+        let storage = temp_storage("roundtrip");
+        let generator = GeneratorKind::Biomes.build(7);
+
+        // Corners and middles of a positive and a negative region
+        let positions = [
+            pos!(0, 0),
+            pos!(3, 5),
+            pos!(7, 7),
+            pos!(-1, -1),
+            pos!(-8, -8),
+            pos!(-3, -6),
+        ];
+        let mut chunks = positions
+            .iter()
+            .map(|chunk_pos| UnloadedChunk::generate(*chunk_pos, &*generator))
+            .collect::<Vec<_>>();
+        chunks[1].entities = vec![1, 2, 3];
+        chunks[4].fog.explore(pos!(2, 3));
+        chunks[4].fog.remember(pos!(2, 3), Tile::grass());
+
+        assert_eq!(ChunkPosition::to_region(pos!(-8, -8)), pos!(-1, -1));
+        assert_eq!(ChunkPosition::to_chunk_in_region(pos!(-8, -8)), pos!(0, 0));
+        assert_eq!(ChunkPosition::to_chunk_in_region(pos!(-1, -1)), pos!(7, 7));
+
+        storage.write_all(&chunks).unwrap();
+        for chunk in &chunks {
+            let read = storage.read_chunk(chunk.pos).unwrap().unwrap();
+            assert_same(&read, chunk);
+        }
+        assert!(storage.read_chunk(pos!(1, 0)).unwrap().is_none());
+        assert!(storage.read_chunk(pos!(-2, -1)).unwrap().is_none());
+        assert!(storage.read_chunk(pos!(8, 0)).unwrap().is_none());
+
+        // Rewriting one chunk keeps the others of its region
+        chunks[0].entities = vec![4, 5];
+        storage.write_all(&chunks[0..1]).unwrap();
+        for chunk in &chunks {
+            let read = storage.read_chunk(chunk.pos).unwrap().unwrap();
+            assert_same(&read, chunk);
+        }
+
+        fs::remove_dir_all(storage.dir()).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use hashbrown::HashMap;
+use specs::Entity;
+
+use crate::world::position::TilePosition;
+use crate::{pos, Position};
+
+/// Entities by the tile and chunk they are on, kept up to date by the `SpatialIndexSystem`.
+///
+/// Rebuilt once movement is done, so entities deleted or spawned later in the same tick are only
+/// gone from or in the index from the next tick on. Callers check the entities they get are alive.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    tiles: HashMap<Position, Vec<Entity>>,
+    chunks: HashMap<Position, Vec<(Entity, Position)>>,
+}
+
+impl SpatialIndex {
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+        self.chunks.clear();
+    }
+
+    pub fn insert(&mut self, entity: Entity, tile_pos: Position) {
+        self.tiles.entry(tile_pos).or_default().push(entity);
+        self.chunks
+            .entry(TilePosition::to_chunk(tile_pos))
+            .or_default()
+            .push((entity, tile_pos));
+    }
+
+    /// Entities on a tile
+    pub fn at(&self, tile_pos: Position) -> &[Entity] {
+        self.tiles.get(&tile_pos).map_or(&[], |entities| entities)
+    }
+
+    /// Entities in a chunk along with their tile
+    pub fn in_chunk(&self, chunk_pos: Position) -> &[(Entity, Position)] {
+        self.chunks.get(&chunk_pos).map_or(&[], |entities| entities)
+    }
+
+    /// Entities on the tiles from `min` to `max`, both inclusive, along with their tile
+    pub fn in_rect(
+        &self,
+        min: Position,
+        max: Position,
+    ) -> impl Iterator<Item = (Entity, Position)> + '_ {
+        let min_chunk = TilePosition::to_chunk(min);
+        let max_chunk = TilePosition::to_chunk(max);
+        (min_chunk.x..=max_chunk.x)
+            .flat_map(move |x| (min_chunk.y..=max_chunk.y).map(move |y| pos!(x, y)))
+            .flat_map(move |chunk_pos| self.in_chunk(chunk_pos).iter().copied())
+            .filter(move |(_, tile_pos)| {
+                tile_pos.x >= min.x
+                    && tile_pos.y >= min.y
+                    && tile_pos.x <= max.x
+                    && tile_pos.y <= max.y
+            })
+    }
+
+    /// Entities at most `radius` tiles from `center` along with their tile, distance as in `Position::dist`
+    pub fn in_radius(
+        &self,
+        center: Position,
+        radius: i32,
+    ) -> impl Iterator<Item = (Entity, Position)> + '_ {
+        self.in_rect(center - pos!(radius, radius), center + pos!(radius, radius))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{Builder, Entity, World, WorldExt};
+
+    use super::SpatialIndex;
+    use crate::world::chunk::Chunk;
+    use crate::{pos, Position};
+
+    /// Index with an entity on each of the given tiles, in order
+    fn index(tiles: &[Position]) -> (SpatialIndex, Vec<Entity>) {
+        let mut world = World::new();
+        let mut index = SpatialIndex::default();
+        let entities = tiles
+            .iter()
+            .map(|tile_pos| {
+                let entity = world.create_entity().build();
+                index.insert(entity, *tile_pos);
+                entity
+            })
+            .collect();
+        (index, entities)
+    }
+
+    fn sorted(found: impl Iterator<Item = (Entity, Position)>) -> Vec<Position> {
+        let mut tiles = found.map(|(_, tile_pos)| tile_pos).collect::<Vec<_>>();
+        tiles.sort_by_key(|tile_pos| (tile_pos.x, tile_pos.y));
+        tiles
+    }
+
+    #[test]
+    fn tiles_and_chunks_hold_their_entities() {
+        let (index, entities) =
+            index(&[pos!(3, 4), pos!(3, 4), pos!(-1, -1), pos!(-Chunk::SIZE, 0)]);
+
+        assert_eq!(index.at(pos!(3, 4)), &entities[0..2]);
+        assert_eq!(index.at(pos!(-1, -1)), &entities[2..3]);
+        assert!(index.at(pos!(0, 0)).is_empty());
+
+        assert_eq!(index.in_chunk(pos!(0, 0)).len(), 2);
+        assert_eq!(index.in_chunk(pos!(-1, -1)), &[(entities[2], pos!(-1, -1))]);
+        assert_eq!(
+            index.in_chunk(pos!(-1, 0)),
+            &[(entities[3], pos!(-Chunk::SIZE, 0))]
+        );
+        assert!(index.in_chunk(pos!(0, -1)).is_empty());
+    }
+
+    #[test]
+    fn rects_cover_negative_tiles() {
+        let (index, _) = index(&[
+            pos!(-5, -5),
+            pos!(-1, 0),
+            pos!(0, -1),
+            pos!(2, 2),
+            pos!(3, 3),
+            pos!(-6, 0),
+        ]);
+
+        assert_eq!(
+            sorted(index.in_rect(pos!(-5, -5), pos!(2, 2))),
+            vec![pos!(-5, -5), pos!(-1, 0), pos!(0, -1), pos!(2, 2)]
+        );
+        assert_eq!(sorted(index.in_rect(pos!(-1, -1), pos!(-1, -1))), vec![]);
+    }
+
+    #[test]
+    fn radius_crosses_chunk_borders() {
+        let edge = Chunk::SIZE;
+        let (index, _) = index(&[
+            pos!(edge - 1, 5),
+            pos!(edge, 5),
+            pos!(edge + 2, 7),
+            pos!(edge + 3, 5),
+            pos!(edge, 2),
+            pos!(-edge, 5),
+        ]);
+
+        assert_eq!(
+            sorted(index.in_radius(pos!(edge, 5), 2)),
+            vec![pos!(edge - 1, 5), pos!(edge, 5), pos!(edge + 2, 7)]
+        );
+        assert_eq!(
+            sorted(index.in_radius(pos!(-edge + 1, 4), 1)),
+            vec![pos!(-edge, 5)]
+        );
+    }
+}
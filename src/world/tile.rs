@@ -1,62 +1,175 @@
 use crate::objects::sprite_atlas::SpriteId;
+use crate::objects::sprite_set::{TileSpriteSet, WallTileSpriteSet};
 use crate::world::direction::{Direction, DirectionalMap};
+use crate::world::light::Light;
+use crate::world::tile_registry::{TileDef, TileRegistry};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+/// Index of a tile type in the `TileRegistry`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TileId(pub u16);
+
+impl TileId {
+    pub fn tree() -> Self {
+        TileRegistry::global().builtin.tree
+    }
+
+    pub fn pine() -> Self {
+        TileRegistry::global().builtin.pine
+    }
+
+    pub fn door() -> Self {
+        TileRegistry::global().builtin.door
+    }
+
+    pub fn rock() -> Self {
+        TileRegistry::global().builtin.rock
+    }
+
+    pub fn wall() -> Self {
+        TileRegistry::global().builtin.wall
+    }
+
+    pub fn torch() -> Self {
+        TileRegistry::global().builtin.torch
+    }
+}
+
+/// A cell of the world, made of a floor with an optional object and overlay on top of it.
+/// Properties of the cell are derived from all of its layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Tile {
-    pub blocked: bool,
-    pub block_sight: bool,
-    pub traversal_cost: i32,
-    pub sprite_id: SpriteId,
+    pub floor: TileId,
+    /// Something standing on the floor, such as a tree, boulder, wall or door
+    pub object: Option<TileId>,
+    /// Decoration drawn on top of everything else
+    pub overlay: Option<TileId>,
+    /// Neighbouring walls, one bit per direction in `DIRECTIONS` order, used to autotile wall objects
+    pub connections: u8,
 }
 
+/// What a layer is made of, walls connect to neighbouring walls when autotiled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TileType {
     Floor,
     Wall,
+    Object,
     Empty,
 }
 
 impl Tile {
     pub const SIZE: f32 = 32.0;
 
-    pub fn empty() -> Self {
+    pub fn new(floor: TileId) -> Self {
         Tile {
-            blocked: false,
-            block_sight: false,
-            traversal_cost: 1,
-            sprite_id: SpriteId::EMPTY,
+            floor,
+            object: None,
+            overlay: None,
+            connections: 0,
         }
     }
 
+    pub fn empty() -> Self {
+        Tile::new(TileRegistry::global().builtin.empty)
+    }
+
     pub fn grass() -> Self {
-        Tile {
-            blocked: false,
-            block_sight: false,
-            traversal_cost: 1,
-            sprite_id: SpriteId::EMPTY,
-        }
+        Tile::new(TileRegistry::global().builtin.grass)
     }
 
-    pub fn rock() -> Self {
-        Tile {
-            blocked: false,
-            block_sight: false,
-            traversal_cost: 1000000,
-            sprite_id: SpriteId::WALL_NESW_OPEN,
-        }
+    pub fn sand() -> Self {
+        Tile::new(TileRegistry::global().builtin.sand)
+    }
+
+    pub fn snow() -> Self {
+        Tile::new(TileRegistry::global().builtin.snow)
+    }
+
+    pub fn marsh() -> Self {
+        Tile::new(TileRegistry::global().builtin.marsh)
+    }
+
+    pub fn gravel() -> Self {
+        Tile::new(TileRegistry::global().builtin.gravel)
+    }
+
+    /// Floor of caves and dungeons, lit as underground
+    pub fn cave_floor() -> Self {
+        Tile::new(TileRegistry::global().builtin.cave_floor)
     }
 
     pub fn wall(neighbours: [bool; 8]) -> Self {
-        let sprite_id = match neighbours {
-            [true, true, true, true, true, true, true, true] => SpriteId::WALL_NESW_OPEN,
-            [true, true, true, true, true, true, true, false] => SpriteId::WALL_NES_OPEN,
-            _ => SpriteId::WALL_NESW_OPEN,
-        };
+        Tile::empty().with_wall(neighbours)
+    }
 
-        Tile {
-            blocked: true,
-            block_sight: true,
-            traversal_cost: 1000000,
-            sprite_id,
+    pub fn with_object(mut self, object: TileId) -> Self {
+        self.object = Some(object);
+        self
+    }
+
+    pub fn with_overlay(mut self, overlay: TileId) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    /// Places a wall on the floor, connected towards the neighbours that are walls
+    pub fn with_wall(mut self, neighbours: [bool; 8]) -> Self {
+        self.object = Some(TileId::wall());
+        self.connections = neighbours
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, wall)| mask | ((*wall as u8) << i));
+        self
+    }
+
+    /// All layers in drawing order
+    pub fn layers(&self) -> impl Iterator<Item = &'static TileDef> {
+        let registry = TileRegistry::global();
+        std::iter::once(self.floor)
+            .chain(self.object)
+            .chain(self.overlay)
+            .map(move |id| registry.def(id))
+    }
+
+    pub fn blocked(&self) -> bool {
+        self.layers().any(|layer| layer.blocked)
+    }
+
+    pub fn block_sight(&self) -> bool {
+        self.layers().any(|layer| layer.block_sight)
+    }
+
+    pub fn traversal_cost(&self) -> i32 {
+        self.layers()
+            .map(|layer| layer.traversal_cost)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Light given off by the topmost glowing layer
+    pub fn light(&self) -> Option<Light> {
+        self.layers().filter_map(|layer| layer.light).last()
+    }
+
+    /// The type of the object if there is one, otherwise of the floor
+    pub fn tile_type(&self) -> TileType {
+        let registry = TileRegistry::global();
+        registry.def(self.object.unwrap_or(self.floor)).tile_type
+    }
+
+    /// The sprite of a layer of this tile, walls are resolved from their connections
+    pub fn sprite_of(&self, layer: &TileDef) -> SpriteId {
+        if layer.tile_type != TileType::Wall {
+            return layer.sprite;
+        }
+
+        let mut neighbours = [TileType::Floor; 8];
+        for (i, neighbour) in neighbours.iter_mut().enumerate() {
+            if self.connections & (1 << i) != 0 {
+                *neighbour = TileType::Wall;
+            }
         }
+        WallTileSpriteSet.get_sprite_id(neighbours)
     }
 }
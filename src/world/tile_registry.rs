@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::objects::sprite_atlas::SpriteId;
+use crate::world::light::Light;
+use crate::world::tile::{Tile, TileId, TileType};
+
+/// Properties shared by every tile of a type, as listed in the tile asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileDef {
+    pub id: TileId,
+    pub name: String,
+    pub sprite: SpriteId,
+    pub tile_type: TileType,
+    #[serde(default)]
+    pub blocked: bool,
+    #[serde(default)]
+    pub block_sight: bool,
+    #[serde(default = "TileDef::default_traversal_cost")]
+    pub traversal_cost: i32,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Light given off by tiles of this type
+    #[serde(default)]
+    pub light: Option<Light>,
+}
+
+impl TileDef {
+    fn default_traversal_cost() -> i32 {
+        1
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// Ids of the tiles generators place by name, resolved once when the registry is loaded
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinTiles {
+    pub empty: TileId,
+    pub grass: TileId,
+    pub sand: TileId,
+    pub snow: TileId,
+    pub marsh: TileId,
+    pub gravel: TileId,
+    pub cave_floor: TileId,
+    pub tree: TileId,
+    pub pine: TileId,
+    pub door: TileId,
+    pub rock: TileId,
+    pub wall: TileId,
+    pub torch: TileId,
+}
+
+#[derive(Debug)]
+pub struct RegistryError {
+    pub message: String,
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for RegistryError {}
+
+impl RegistryError {
+    fn new<S: Into<String>>(message: S) -> Self {
+        RegistryError {
+            message: message.into(),
+        }
+    }
+}
+
+static REGISTRY: OnceLock<TileRegistry> = OnceLock::new();
+
+/// All tile types, chunks only store the `TileId`s of their tiles.
+///
+/// The registry is global so tiles can be resolved anywhere, including the chunk worker threads.
+/// It is installed once at startup, falling back to the tile asset compiled into the binary.
+#[derive(Debug)]
+pub struct TileRegistry {
+    defs: Vec<Option<TileDef>>,
+    names: HashMap<String, TileId>,
+    pub builtin: BuiltinTiles,
+}
+
+impl TileRegistry {
+    pub const FILE: &'static str = "tiles.ron";
+    const BUILTIN_SOURCE: &'static str = include_str!("../../assets/tiles.ron");
+
+    pub fn from_ron(source: &str) -> Result<Self, RegistryError> {
+        let defs: Vec<TileDef> = ron::de::from_str(source)
+            .map_err(|e| RegistryError::new(format!("Invalid tile definitions: {}", e)))?;
+        TileRegistry::from_defs(defs)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RegistryError> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| RegistryError::new(format!("Failed to read {:?}: {}", path, e)))?;
+        TileRegistry::from_ron(&source)
+    }
+
+    pub fn from_defs(defs: Vec<TileDef>) -> Result<Self, RegistryError> {
+        let mut by_id = Vec::<Option<TileDef>>::new();
+        let mut names = HashMap::new();
+
+        for def in defs {
+            let index = def.id.0 as usize;
+            if by_id.len() <= index {
+                by_id.resize(index + 1, None);
+            }
+            if let Some(existing) = &by_id[index] {
+                return Err(RegistryError::new(format!(
+                    "Tiles {:?} and {:?} share the id {}",
+                    existing.name, def.name, def.id.0
+                )));
+            }
+            if names.insert(def.name.clone(), def.id).is_some() {
+                return Err(RegistryError::new(format!(
+                    "Tile name {:?} is defined more than once",
+                    def.name
+                )));
+            }
+            by_id[index] = Some(def);
+        }
+
+        let required = |name: &str| {
+            names
+                .get(name)
+                .copied()
+                .ok_or_else(|| RegistryError::new(format!("Missing required tile {:?}", name)))
+        };
+        let builtin = BuiltinTiles {
+            empty: required("empty")?,
+            grass: required("grass")?,
+            sand: required("sand")?,
+            snow: required("snow")?,
+            marsh: required("marsh")?,
+            gravel: required("gravel")?,
+            cave_floor: required("cave_floor")?,
+            tree: required("tree")?,
+            pine: required("pine")?,
+            door: required("door")?,
+            rock: required("rock")?,
+            wall: required("wall")?,
+            torch: required("torch")?,
+        };
+
+        Ok(TileRegistry {
+            defs: by_id,
+            names,
+            builtin,
+        })
+    }
+
+    /// Makes `registry` the global registry, fails if one is already in use
+    pub fn install(registry: TileRegistry) -> Result<(), RegistryError> {
+        REGISTRY
+            .set(registry)
+            .map_err(|_| RegistryError::new("A tile registry is already installed"))
+    }
+
+    pub fn global() -> &'static TileRegistry {
+        REGISTRY.get_or_init(|| {
+            TileRegistry::from_ron(TileRegistry::BUILTIN_SOURCE)
+                .expect("Builtin tile definitions are invalid")
+        })
+    }
+
+    pub fn get(&self, id: TileId) -> Option<&TileDef> {
+        self.defs.get(id.0 as usize).and_then(|def| def.as_ref())
+    }
+
+    /// Panics on unknown ids, chunks are validated when loaded so every stored id is known
+    pub fn def(&self, id: TileId) -> &TileDef {
+        self.get(id)
+            .unwrap_or_else(|| panic!("Unknown tile id {}", id.0))
+    }
+
+    pub fn id(&self, name: &str) -> Option<TileId> {
+        self.names.get(name).copied()
+    }
+
+    /// Checks that every tile id used by `tiles` is registered
+    pub fn validate<'a, I: IntoIterator<Item = &'a Tile>>(
+        &self,
+        tiles: I,
+    ) -> Result<(), RegistryError> {
+        for tile in tiles {
+            let ids = std::iter::once(tile.floor)
+                .chain(tile.object)
+                .chain(tile.overlay);
+            for id in ids {
+                if self.get(id).is_none() {
+                    return Err(RegistryError::new(format!("Unknown tile id {}", id.0)));
+                }
+            }
+        }
+        Ok(())
+    }
+}